@@ -0,0 +1,621 @@
+//! A `systemctl` work-alike built on this crate, and an end-to-end check of its `Systemd1Manager` calls against
+//! a running systemd.
+//!
+//! ```text
+//! dbus-systemctl [--user] [--no-block] [--output=json] [--job-mode=MODE] [--lines=N] [--all] [--state=STATE]
+//!                [--type=TYPE] COMMAND [ARGS]...
+//! ```
+//!
+//! Exit codes follow systemctl: `is-active`, `is-enabled` and `is-failed` exit 0 when at least one unit is in
+//! the state asked about, `status` exits 3 when a unit isn't active and 4 when it doesn't exist, and any other
+//! failure exits 1.
+use anyhow::{anyhow, Result};
+use dbus::arg::{ArgType, PropMap, RefArg};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, path::Path, str::FromStr};
+
+use dbus_systemd::{glob_match, Mode, Systemd1Manager, SystemdManager, UnitQuery};
+
+const UNIT_INTERFACE: &str = "org.freedesktop.systemd1.Unit";
+const NO_SUCH_UNIT: &str = "org.freedesktop.systemd1.NoSuchUnit";
+// Unit file states `is-enabled` reports success for.
+const ENABLED_STATES: &[&str] = &[
+    "enabled",
+    "enabled-runtime",
+    "static",
+    "indirect",
+    "alias",
+    "generated",
+    "transient",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+struct Cli {
+    user: bool,
+    no_block: bool,
+    json: bool,
+    mode: Mode,
+    lines: usize,
+    all: bool,
+    states: Vec<String>,
+    unit_types: Vec<String>,
+    command: String,
+    args: Vec<String>,
+}
+
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Cli> {
+    let mut cli = Cli {
+        user: false,
+        no_block: false,
+        json: false,
+        mode: Mode::Replace,
+        lines: 10,
+        all: false,
+        states: vec![],
+        unit_types: vec![],
+        command: String::new(),
+        args: vec![],
+    };
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if arg.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |flag: &str| match inline.clone() {
+            Some(value) => Ok(value),
+            None => args.next().ok_or_else(|| anyhow!("{} needs a value", flag)),
+        };
+        match flag.as_str() {
+            "--user" => cli.user = true,
+            "--system" => cli.user = false,
+            "--no-block" => cli.no_block = true,
+            "--output" | "-o" => {
+                cli.json = match value(&flag)?.as_str() {
+                    "json" => true,
+                    "short" => false,
+                    other => return Err(anyhow!("unsupported output {}", other)),
+                }
+            }
+            "--job-mode" => {
+                let mode = value(&flag)?;
+                cli.mode = Mode::from_str(&mode).map_err(|_| anyhow!("unknown job mode {}", mode))?;
+            }
+            "--lines" | "-n" => cli.lines = value(&flag)?.parse()?,
+            "--all" | "-a" => cli.all = true,
+            "--state" => cli.states.extend(value(&flag)?.split(',').map(String::from)),
+            "--type" | "-t" => cli.unit_types.extend(value(&flag)?.split(',').map(String::from)),
+            flag if flag.starts_with('-') && cli.command.is_empty() => return Err(anyhow!("unknown option {}", flag)),
+            _ if cli.command.is_empty() => cli.command = arg,
+            _ => cli.args.push(arg),
+        }
+    }
+    if cli.command.is_empty() {
+        cli.command = "list-units".to_string();
+    }
+    Ok(cli)
+}
+
+struct Systemctl {
+    manager: SystemdManager,
+    cli: Cli,
+}
+
+impl Systemctl {
+    async fn run(&self) -> Result<i32> {
+        let units = self.cli.args.clone();
+        let needs_units = !matches!(
+            self.cli.command.as_str(),
+            "list-units" | "list-unit-files" | "list-jobs" | "daemon-reload"
+        );
+        if needs_units && units.is_empty() {
+            return Err(anyhow!("{} needs at least one unit", self.cli.command));
+        }
+        match self.cli.command.as_str() {
+            "list-units" => self.list_units(&units).await,
+            "list-unit-files" => self.list_unit_files(&units).await,
+            "list-jobs" => self.list_jobs().await,
+            "status" => self.status(&units).await,
+            "start" | "stop" | "restart" | "reload" => self.queue_jobs(&units).await,
+            "enable" | "disable" | "mask" | "unmask" => self.change_unit_files(&units).await,
+            "show" => self.show(&units).await,
+            "cat" => self.cat(&units).await,
+            "daemon-reload" => {
+                self.manager.reload().await.map_err(error)?;
+                Ok(0)
+            }
+            "is-active" => self.is_state(&units, &["active", "reloading"], 3).await,
+            "is-failed" => self.is_state(&units, &["failed"], 1).await,
+            "is-enabled" => self.is_enabled(&units).await,
+            other => Err(anyhow!("unknown command {}", other)),
+        }
+    }
+
+    async fn list_units(&self, patterns: &[String]) -> Result<i32> {
+        let mut query = UnitQuery::new().all(self.cli.all);
+        for pattern in patterns.iter() {
+            query = query.pattern(pattern);
+        }
+        for state in self.cli.states.iter() {
+            query = query.state(state);
+        }
+        for unit_type in self.cli.unit_types.iter() {
+            query = query.unit_type(unit_type);
+        }
+        let units = self.manager.query_units(&query).await.map_err(error)?;
+        if self.cli.json {
+            let units: Vec<Value> = units
+                .iter()
+                .map(|u| json!({"unit": u.name, "load": u.loaded, "active": u.active, "sub": u.status, "description": u.description}))
+                .collect();
+            println!("{}", Value::Array(units));
+        } else {
+            let rows: Vec<Vec<String>> = units
+                .iter()
+                .map(|u| {
+                    vec![
+                        u.name.clone(),
+                        u.loaded.clone(),
+                        u.active.clone(),
+                        u.status.clone(),
+                        u.description.clone(),
+                    ]
+                })
+                .collect();
+            print_table(&["UNIT", "LOAD", "ACTIVE", "SUB", "DESCRIPTION"], &rows);
+            println!("\n{} loaded units listed.", units.len());
+        }
+        Ok(0)
+    }
+
+    async fn list_unit_files(&self, patterns: &[String]) -> Result<i32> {
+        let mut files: Vec<(String, String)> = self
+            .manager
+            .list_unit_files()
+            .await
+            .map_err(error)?
+            .into_iter()
+            .map(|(path, state)| (file_name(&path), state))
+            .filter(|(name, _)| patterns.is_empty() || patterns.iter().any(|p| glob_match(p, name)))
+            .collect();
+        files.sort();
+        if self.cli.json {
+            let files: Vec<Value> = files
+                .iter()
+                .map(|(name, state)| json!({"unit_file": name, "state": state}))
+                .collect();
+            println!("{}", Value::Array(files));
+        } else {
+            let rows: Vec<Vec<String>> = files
+                .iter()
+                .map(|(name, state)| vec![name.clone(), state.clone()])
+                .collect();
+            print_table(&["UNIT FILE", "STATE"], &rows);
+            println!("\n{} unit files listed.", files.len());
+        }
+        Ok(0)
+    }
+
+    async fn list_jobs(&self) -> Result<i32> {
+        let jobs = self.manager.list_jobs().await.map_err(error)?;
+        if self.cli.json {
+            let jobs: Vec<Value> = jobs
+                .iter()
+                .map(|(id, unit, job_type, state, _, _)| json!({"job": id, "unit": unit, "type": job_type, "state": state}))
+                .collect();
+            println!("{}", Value::Array(jobs));
+        } else if jobs.is_empty() {
+            println!("No jobs running.");
+        } else {
+            let rows: Vec<Vec<String>> = jobs
+                .iter()
+                .map(|(id, unit, job_type, state, _, _)| {
+                    vec![id.to_string(), unit.clone(), job_type.clone(), state.clone()]
+                })
+                .collect();
+            print_table(&["JOB", "UNIT", "TYPE", "STATE"], &rows);
+            println!("\n{} jobs listed.", jobs.len());
+        }
+        Ok(0)
+    }
+
+    async fn status(&self, units: &[String]) -> Result<i32> {
+        let mut code = 0;
+        let mut reports = vec![];
+        for (i, name) in units.iter().enumerate() {
+            let report = match self.manager.unit_status(name, self.cli.lines).await {
+                Ok(report) => report,
+                Err(e) if is_no_such_unit(&e) => {
+                    eprintln!("Unit {} could not be found.", name);
+                    code = code.max(4);
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("{}", error(e));
+                    code = code.max(1);
+                    continue;
+                }
+            };
+            if report.load_state == "not-found" {
+                eprintln!("Unit {} could not be found.", name);
+                code = code.max(4);
+                continue;
+            }
+            if report.active_state != "active" && report.active_state != "reloading" {
+                code = code.max(3);
+            }
+            if self.cli.json {
+                reports.push(json!({
+                    "unit": report.name,
+                    "description": report.description,
+                    "load_state": report.load_state,
+                    "fragment_path": report.fragment_path,
+                    "unit_file_state": report.unit_file_state,
+                    "active_state": report.active_state,
+                    "sub_state": report.sub_state,
+                    "result": report.result,
+                    "main_pid": report.main_pid,
+                    "tasks": report.tasks,
+                    "memory": report.memory,
+                    "cpu_nsec": report.cpu.map(|cpu| cpu.as_nanos() as u64),
+                    "control_group": report.control_group,
+                }));
+            } else {
+                if i > 0 {
+                    println!();
+                }
+                print!("{}", report);
+            }
+        }
+        if self.cli.json {
+            println!("{}", Value::Array(reports));
+        }
+        Ok(code)
+    }
+
+    async fn queue_jobs(&self, units: &[String]) -> Result<i32> {
+        let mode = &self.cli.mode;
+        for name in units.iter() {
+            let queue = async {
+                match self.cli.command.as_str() {
+                    "start" => self.manager.start_unit(name, mode).await,
+                    "stop" => self.manager.stop_unit(name, mode).await,
+                    "restart" => self.manager.restart_unit(name, mode).await,
+                    _ => self.manager.reload_unit(name, mode).await,
+                }
+            };
+            if self.cli.no_block {
+                queue.await.map_err(error)?;
+                continue;
+            }
+            let result = self.manager.await_job(queue).await.map_err(error)?;
+            if result != "done" {
+                return Err(anyhow!(
+                    "Job for {} failed with result {}. See \"dbus-systemctl status {}\" for details.",
+                    name,
+                    result,
+                    name
+                ));
+            }
+        }
+        Ok(0)
+    }
+
+    async fn change_unit_files(&self, units: &[String]) -> Result<i32> {
+        let files: Vec<&str> = units.iter().map(String::as_str).collect();
+        let changes = match self.cli.command.as_str() {
+            "enable" => self
+                .manager
+                .enable_unit_files(files, false, false)
+                .await
+                .map(|(_, changes)| changes),
+            "disable" => self.manager.disable_unit_files(files, false).await,
+            "mask" => self.manager.mask_unit_files(files, false, false).await,
+            _ => self.manager.unmask_unit_files(files, false).await,
+        }
+        .map_err(error)?;
+        // Like systemctl, make the manager pick up the changed symlinks right away.
+        self.manager.reload().await.map_err(error)?;
+
+        if self.cli.json {
+            let changes: Vec<Value> = changes
+                .iter()
+                .map(|(change, file, destination)| json!({"type": change, "file": file, "destination": destination}))
+                .collect();
+            println!("{}", Value::Array(changes));
+        } else {
+            for (change, file, destination) in changes.iter() {
+                match change.as_str() {
+                    "symlink" => eprintln!("Created symlink {} \u{2192} {}.", file, destination),
+                    "unlink" => eprintln!("Removed \"{}\".", file),
+                    other => eprintln!("{} {} {}", other, file, destination),
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    async fn properties(&self, name: &str) -> Result<BTreeMap<String, String>> {
+        let mut properties = BTreeMap::new();
+        let mut interfaces = vec![UNIT_INTERFACE.to_string()];
+        if let Some(suffix) = name.rsplit('.').next() {
+            let mut chars = suffix.chars();
+            if let Some(first) = chars.next() {
+                interfaces.push(format!(
+                    "org.freedesktop.systemd1.{}{}",
+                    first.to_ascii_uppercase(),
+                    chars.as_str()
+                ));
+            }
+        }
+        for (i, interface) in interfaces.iter().enumerate() {
+            match self.manager.get_unit_properties(name, interface).await {
+                Ok(values) => properties.extend(format_properties(&values)),
+                // Not every unit type has its own interface (targets, devices).
+                Err(e) if i > 0 => tracing::debug!("{}: {:?}", interface, e),
+                Err(e) => return Err(error(e)),
+            }
+        }
+        Ok(properties)
+    }
+
+    async fn show(&self, units: &[String]) -> Result<i32> {
+        let mut shown = vec![];
+        for (i, name) in units.iter().enumerate() {
+            let properties = self.properties(name).await?;
+            if self.cli.json {
+                shown.push(json!(properties));
+            } else {
+                if i > 0 {
+                    println!();
+                }
+                for (key, value) in properties.iter() {
+                    println!("{}={}", key, value);
+                }
+            }
+        }
+        if self.cli.json {
+            println!("{}", Value::Array(shown));
+        }
+        Ok(0)
+    }
+
+    async fn cat(&self, units: &[String]) -> Result<i32> {
+        for (i, name) in units.iter().enumerate() {
+            let unit = self
+                .manager
+                .get_unit_properties(name, UNIT_INTERFACE)
+                .await
+                .map_err(error)?;
+            let mut paths = vec![];
+            if let Some(fragment) = unit
+                .get("FragmentPath")
+                .and_then(|v| v.0.as_str())
+                .filter(|p| !p.is_empty())
+            {
+                paths.push(fragment.to_string());
+            }
+            if let Some(drop_ins) = unit.get("DropInPaths").and_then(|v| v.0.as_iter()) {
+                paths.extend(drop_ins.filter_map(|p| p.as_str().map(String::from)));
+            }
+            if paths.is_empty() {
+                return Err(anyhow!("No files found for {}.", name));
+            }
+            for (j, path) in paths.iter().enumerate() {
+                if i > 0 || j > 0 {
+                    println!();
+                }
+                println!("# {}", path);
+                print!("{}", std::fs::read_to_string(path)?);
+            }
+        }
+        Ok(0)
+    }
+
+    /// Exits 0 if any unit is in one of `states`, `otherwise` if none is; missing units count as inactive.
+    async fn is_state(&self, units: &[String], states: &[&str], otherwise: i32) -> Result<i32> {
+        let mut found = vec![];
+        for name in units.iter() {
+            let state = match self.manager.get_unit_properties(name, UNIT_INTERFACE).await {
+                Ok(unit) => unit
+                    .get("ActiveState")
+                    .and_then(|v| v.0.as_str())
+                    .unwrap_or("unknown")
+                    .to_string(),
+                Err(e) if is_no_such_unit(&e) => "inactive".to_string(),
+                Err(e) => return Err(error(e)),
+            };
+            println!("{}", state);
+            found.push(state);
+        }
+        Ok(state_exit_code(&found, states, otherwise))
+    }
+
+    async fn is_enabled(&self, units: &[String]) -> Result<i32> {
+        let mut code = 1;
+        for name in units.iter() {
+            let state = match self.manager.get_unit_file_state(name).await {
+                Ok(state) => state,
+                Err(e) if is_no_such_unit(&e) => "not-found".to_string(),
+                Err(e) => return Err(error(e)),
+            };
+            if ENABLED_STATES.contains(&state.as_str()) {
+                code = 0;
+            }
+            println!("{}", state);
+        }
+        Ok(code)
+    }
+}
+
+fn state_exit_code(found: &[String], states: &[&str], otherwise: i32) -> i32 {
+    if found.iter().any(|state| states.contains(&state.as_str())) {
+        0
+    } else {
+        otherwise
+    }
+}
+
+/// Property values the way `systemctl show` prints them: booleans as yes/no, arrays space-separated.
+fn format_properties(properties: &PropMap) -> BTreeMap<String, String> {
+    properties
+        .iter()
+        .map(|(name, value)| (name.clone(), format_value(&*value.0)))
+        .collect()
+}
+
+fn format_value(value: &dyn RefArg) -> String {
+    match value.arg_type() {
+        ArgType::Boolean => if value.as_i64() == Some(1) { "yes" } else { "no" }.to_string(),
+        ArgType::String | ArgType::ObjectPath | ArgType::Signature => value.as_str().unwrap_or_default().to_string(),
+        ArgType::Array if &*value.signature() == "ay" => value
+            .as_iter()
+            .map(|bytes| bytes.filter_map(|b| b.as_u64()).map(|b| format!("{:02x}", b)).collect())
+            .unwrap_or_default(),
+        ArgType::Array | ArgType::Struct | ArgType::Variant => match value.as_iter() {
+            Some(items) => items.map(format_value).collect::<Vec<String>>().join(" "),
+            None => String::new(),
+        },
+        ArgType::Double => value.as_f64().map(|v| v.to_string()).unwrap_or_default(),
+        ArgType::UInt64 => value.as_u64().map(|v| v.to_string()).unwrap_or_default(),
+        _ => value.as_i64().map(|v| v.to_string()).unwrap_or_default(),
+    }
+}
+
+fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in rows.iter() {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let last = cells.len() - 1;
+        let cells: Vec<String> = cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                if i == last {
+                    cell.to_string()
+                } else {
+                    format!("{:<width$}", cell, width = widths[i])
+                }
+            })
+            .collect();
+        println!("{}", cells.join(" "));
+    };
+    line(header.to_vec());
+    for row in rows.iter() {
+        line(row.iter().map(String::as_str).collect());
+    }
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
+fn is_no_such_unit(e: &dbus::MethodErr) -> bool {
+    e.errorname() == NO_SUCH_UNIT
+}
+
+fn error(e: dbus::MethodErr) -> anyhow::Error {
+    anyhow!("{}", e.description())
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt().with_writer(std::io::stderr).init();
+    let code = match parse_args(std::env::args().skip(1)) {
+        Ok(cli) => {
+            let manager = if cli.user {
+                SystemdManager::user()
+            } else {
+                SystemdManager::default()
+            };
+            match (Systemctl { manager, cli }).run().await {
+                Ok(code) => code,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    1
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    };
+    std::process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::{PropMap, RefArg, Variant};
+
+    use super::{format_properties, parse_args, state_exit_code, Mode};
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn parses_systemctl_style_arguments() {
+        let cli = parse_args(args("--user --no-block --output=json restart a.service b.service")).unwrap();
+        assert!(cli.user && cli.no_block && cli.json);
+        assert_eq!(cli.command, "restart");
+        assert_eq!(cli.args, vec!["a.service", "b.service"]);
+
+        let cli = parse_args(args("status -n 3 --job-mode fail sshd.service")).unwrap();
+        assert_eq!(cli.lines, 3);
+        assert_eq!(cli.mode, Mode::Fail);
+
+        let cli = parse_args(args("list-units -a --type service,timer --state=failed ssh*")).unwrap();
+        assert!(cli.all);
+        assert_eq!(cli.unit_types, vec!["service", "timer"]);
+        assert_eq!(cli.states, vec!["failed"]);
+        assert_eq!(cli.args, vec!["ssh*"]);
+
+        assert_eq!(parse_args(args("")).unwrap().command, "list-units");
+        assert!(parse_args(args("--output=yaml list-units")).is_err());
+        assert!(parse_args(args("--job-mode=whenever start a.service")).is_err());
+        assert!(parse_args(args("--frobnicate list-units")).is_err());
+    }
+
+    #[test]
+    fn maps_states_to_systemctl_exit_codes() {
+        let states = |states: &[&str]| states.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let active = &["active", "reloading"];
+        assert_eq!(state_exit_code(&states(&["inactive", "reloading"]), active, 3), 0);
+        // A unit that isn't loaded reads as inactive: is-active says 3, is-failed says 1.
+        assert_eq!(state_exit_code(&states(&["inactive"]), active, 3), 3);
+        assert_eq!(state_exit_code(&states(&["inactive"]), &["failed"], 1), 1);
+        assert_eq!(state_exit_code(&states(&["failed"]), &["failed"], 1), 0);
+        assert_eq!(state_exit_code(&[], active, 3), 3);
+    }
+
+    #[test]
+    fn formats_properties_like_systemctl_show() {
+        let mut properties = PropMap::new();
+        let mut insert = |name: &str, value: Box<dyn RefArg>| {
+            properties.insert(name.to_string(), Variant(value));
+        };
+        insert("CanStart", Box::new(true));
+        insert("MainPID", Box::new(42u32));
+        insert("Id", Box::new("sshd.service".to_string()));
+        insert(
+            "Names",
+            Box::new(vec!["sshd.service".to_string(), "ssh.service".to_string()]),
+        );
+        insert("InvocationID", Box::new(vec![0xabu8, 0x01]));
+
+        let shown = format_properties(&properties);
+        assert_eq!(shown["CanStart"], "yes");
+        assert_eq!(shown["MainPID"], "42");
+        assert_eq!(shown["Id"], "sshd.service");
+        assert_eq!(shown["Names"], "sshd.service ssh.service");
+        assert_eq!(shown["InvocationID"], "ab01");
+    }
+}
@@ -0,0 +1,121 @@
+//! Serves systemd metrics for Prometheus on `/metrics`.
+//!
+//! ```text
+//! dbus-systemd-exporter [--listen-address 127.0.0.1:9558] [--include GLOB]... [--exclude GLOB]...
+//! ```
+use anyhow::{anyhow, Context, Result};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+use dbus_systemd::{Exporter, SystemdManager, UnitFilter};
+
+const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:9558";
+const MAX_REQUEST_HEAD: usize = 8192;
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+struct Args {
+    listen_address: String,
+    filter: UnitFilter,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Args> {
+    let mut parsed = Args {
+        listen_address: DEFAULT_LISTEN_ADDRESS.to_string(),
+        filter: UnitFilter::default(),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| anyhow!("{} needs a value", name));
+        match arg.as_str() {
+            "--listen-address" => parsed.listen_address = value(&arg)?,
+            "--include" => parsed.filter.include.extend(value(&arg)?.split(',').map(String::from)),
+            "--exclude" => parsed.filter.exclude.extend(value(&arg)?.split(',').map(String::from)),
+            "--help" | "-h" => {
+                println!(
+                    "usage: dbus-systemd-exporter [--listen-address ADDR] [--include GLOB]... [--exclude GLOB]..."
+                );
+                std::process::exit(0);
+            }
+            other => return Err(anyhow!("unknown argument {}", other)),
+        }
+    }
+    Ok(parsed)
+}
+
+async fn serve(exporter: &Exporter, mut stream: TcpStream) -> Result<()> {
+    let mut head = vec![];
+    let mut buffer = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || head.len() + read > MAX_REQUEST_HEAD {
+            return Err(anyhow!("incomplete or oversized request"));
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    let request_line = String::from_utf8_lossy(&head)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4", exporter.scrape().await),
+        ("GET", "/") => (
+            "200 OK",
+            "text/plain",
+            "dbus-systemd-exporter: metrics are at /metrics\n".to_string(),
+        ),
+        ("GET", _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "only GET is supported\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let Args { listen_address, filter } = parse_args(std::env::args().skip(1))?;
+    let exporter = Exporter::new(SystemdManager::default(), filter);
+
+    let watcher = exporter.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = watcher.watch().await {
+                warn!("watching systemd failed: {:?}", e);
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    });
+
+    let listener = TcpListener::bind(&listen_address)
+        .await
+        .with_context(|| format!("listening on {}", listen_address))?;
+    info!("serving metrics on http://{}/metrics", listen_address);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let exporter = exporter.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(&exporter, stream).await {
+                debug!("{}: {:?}", peer, e);
+            }
+        });
+    }
+}
@@ -1,9 +1,17 @@
 use dbus::{arg, nonblock::SyncConnection};
-use dbus_tokio::connection::new_system_sync;
+use dbus_tokio::connection::{new_session_sync, new_system_sync};
 use futures::future::{abortable, AbortHandle};
 use std::{ops::Deref, sync::Arc};
 use tracing::{debug, info};
 
+/// Which bus to connect to: the system bus for the system manager, the session bus for the user's manager.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Bus {
+    #[default]
+    System,
+    Session,
+}
+
 #[derive(Clone)]
 pub struct DBusConnection {
     connection: Arc<SyncConnection>,
@@ -20,7 +28,14 @@ impl std::fmt::Debug for DBusConnection {
 
 impl DBusConnection {
     pub fn new() -> Self {
-        let (resource, connection) = new_system_sync().unwrap();
+        Self::connect(Bus::System)
+    }
+
+    pub fn connect(bus: Bus) -> Self {
+        let (resource, connection) = match bus {
+            Bus::System => new_system_sync().unwrap(),
+            Bus::Session => new_session_sync().unwrap(),
+        };
         let (abortable_resource, connection_abort_handle) = abortable(resource);
 
         tokio::spawn(async {
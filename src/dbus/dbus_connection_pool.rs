@@ -3,14 +3,20 @@ use dbus::nonblock::Proxy as DBusProxy;
 use deadpool::managed::{Manager as ConnectionManager, PoolConfig, RecycleError, RecycleResult};
 use std::time::Duration;
 
-use super::DBusConnection;
+use super::{Bus, DBusConnection};
 
 pub type DBusConnectionPool = deadpool::managed::Pool<DBusConnection, anyhow::Error>;
 
-#[derive(Clone, Debug)]
-pub struct DbusConnectionManager;
+#[derive(Clone, Debug, Default)]
+pub struct DbusConnectionManager {
+    bus: Bus,
+}
 
 impl DbusConnectionManager {
+    pub fn new(bus: Bus) -> Self {
+        Self { bus }
+    }
+
     pub async fn make_dbus_proxy(
         destination: String,
         path: String,
@@ -29,7 +35,7 @@ impl DbusConnectionManager {
 #[async_trait::async_trait]
 impl ConnectionManager<DBusConnection, anyhow::Error> for DbusConnectionManager {
     async fn create(&self) -> Result<DBusConnection, anyhow::Error> {
-        Ok(DBusConnection::new())
+        Ok(DBusConnection::connect(self.bus))
     }
 
     // This is called when the pool is about to recycle a connection; a return of Ok(()) means its ok to recycle.
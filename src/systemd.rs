@@ -1,11 +1,61 @@
+mod calendar;
+mod cgroup;
+mod fd_store;
 mod job;
+mod journal;
+mod journal_export;
+mod journal_layer;
+mod metrics;
 mod mode;
+mod notify;
+mod process;
+mod properties;
+mod reconcile;
+mod resource_control;
+mod run;
+mod scope;
+#[cfg(feature = "serde")]
+mod serde_method_result;
+#[cfg(feature = "serde")]
+mod serde_path;
+mod socket_activation;
+mod status;
 mod systemd1_manager;
 mod systemd_manager;
+mod time_span;
+mod timer;
+mod transaction;
+mod transient;
 mod unit;
+mod unit_query;
+mod usage;
+mod watchdog;
 
+pub use calendar::*;
+pub use cgroup::*;
+pub use fd_store::*;
 pub use job::*;
+pub use journal::*;
+pub use journal_export::*;
+pub use journal_layer::*;
+pub use metrics::*;
 pub use mode::*;
+pub use notify::*;
+pub use process::*;
+pub use properties::*;
+pub use reconcile::*;
+pub use resource_control::*;
+pub use run::*;
+pub use scope::*;
+pub use socket_activation::*;
+pub use status::*;
 pub use systemd1_manager::*;
 pub use systemd_manager::*;
+pub use time_span::*;
+pub use timer::*;
+pub use transaction::*;
+pub use transient::*;
 pub use unit::*;
+pub use unit_query::*;
+pub use usage::*;
+pub use watchdog::*;
@@ -0,0 +1,742 @@
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, LocalResult, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const USEC_PER_SEC: u64 = 1_000_000;
+const WEEKDAYS: &[&str] = &["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const WEEKDAY_NAMES: &[&str] = &[
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+const MIN_YEAR: u64 = 1970;
+const MAX_YEAR: u64 = 2199;
+
+const SHORTHANDS: &[(&str, &str)] = &[
+    ("minutely", "*-*-* *:*:00"),
+    ("hourly", "*-*-* *:00:00"),
+    ("daily", "*-*-* 00:00:00"),
+    ("monthly", "*-*-01 00:00:00"),
+    ("weekly", "Mon *-*-* 00:00:00"),
+    ("yearly", "*-01-01 00:00:00"),
+    ("annually", "*-01-01 00:00:00"),
+    ("quarterly", "*-01,04,07,10-01 00:00:00"),
+    ("semiannually", "*-01,07-01 00:00:00"),
+];
+
+/// One comma-separated element of a calendar field: `start`, `start..stop`, `start/repeat` or `start..stop/repeat`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Component {
+    start: u64,
+    stop: Option<u64>,
+    repeat: Option<u64>,
+}
+
+impl Component {
+    /// Smallest matching value in `value..=max`.
+    fn next_match(&self, value: u64, max: u64) -> Option<u64> {
+        let last = self
+            .stop
+            .unwrap_or(if self.repeat.is_some() { max } else { self.start })
+            .min(max);
+        let candidate = if value <= self.start {
+            self.start
+        } else {
+            match self.repeat {
+                Some(repeat) => self.start + (value - self.start).div_ceil(repeat) * repeat,
+                None if self.stop.is_some() => value,
+                None => return None,
+            }
+        };
+        if candidate <= last {
+            Some(candidate)
+        } else {
+            None
+        }
+    }
+}
+
+/// A list of components; empty means `*`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Chain(Vec<Component>);
+
+impl Chain {
+    fn next_match(&self, value: u64, max: u64) -> Option<u64> {
+        if self.0.is_empty() {
+            return if value <= max { Some(value) } else { None };
+        }
+        self.0.iter().filter_map(|c| c.next_match(value, max)).min()
+    }
+
+    fn matches(&self, value: u64) -> bool {
+        self.next_match(value, value) == Some(value)
+    }
+
+    fn format(&self, f: &mut fmt::Formatter<'_>, width: usize, scale: u64) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("*");
+        }
+        let number = |f: &mut fmt::Formatter<'_>, value: u64| -> fmt::Result {
+            write!(f, "{:0width$}", value / scale, width = width)?;
+            if !value.is_multiple_of(scale) {
+                write!(f, ".{:06}", value % scale)?;
+            }
+            Ok(())
+        };
+        for (i, component) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            number(f, component.start)?;
+            if let Some(stop) = component.stop {
+                f.write_str("..")?;
+                number(f, stop)?;
+            }
+            if let Some(repeat) = component.repeat {
+                write!(f, "/{}", repeat / scale)?;
+                if !repeat.is_multiple_of(scale) {
+                    write!(f, ".{:06}", repeat % scale)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A systemd calendar event (OnCalendar=), e.g. `Mon..Fri *-*-* 09:00:00` or `weekly`.
+///
+/// Parsing and `Display` follow `systemd-analyze calendar`: shorthands are expanded and the result is printed in
+/// the normalized `WEEKDAYS YEAR-MONTH-DAY HOUR:MINUTE:SECOND [TIMEZONE]` form.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CalendarSpec {
+    /// Bit 0 is Monday; `None` matches every day.
+    weekdays: Option<u8>,
+    year: Chain,
+    month: Chain,
+    day: Chain,
+    /// Set by `~`: days count back from the end of the month.
+    end_of_month: bool,
+    hour: Chain,
+    minute: Chain,
+    /// In microseconds, so fractional seconds are exact.
+    second: Chain,
+    timezone: Option<String>,
+}
+
+impl CalendarSpec {
+    /// `None` when the spec uses the local timezone.
+    pub fn timezone(&self) -> Option<&str> {
+        self.timezone.as_deref()
+    }
+
+    /// The first time strictly after `after` that the event elapses, or `None` if it never does again.
+    pub fn next_elapse(&self, after: SystemTime) -> Option<SystemTime> {
+        let after = after.duration_since(UNIX_EPOCH).ok()?;
+        let after = Utc
+            .timestamp_opt(after.as_secs() as i64, after.subsec_nanos())
+            .single()?;
+        let next = match self.timezone.as_deref() {
+            None => self.next_in(&chrono::Local, after.naive_utc())?,
+            Some("UTC") => self.next_in(&Utc, after.naive_utc())?,
+            Some(name) => self.next_in(&name.parse::<Tz>().ok()?, after.naive_utc())?,
+        };
+        let micros = next.timestamp() as u64 * USEC_PER_SEC + next.timestamp_subsec_micros() as u64;
+        Some(UNIX_EPOCH + Duration::from_micros(micros))
+    }
+
+    fn next_in<Z: TimeZone>(&self, tz: &Z, after_utc: NaiveDateTime) -> Option<chrono::DateTime<Utc>> {
+        let mut local = tz.from_utc_datetime(&after_utc).naive_local() + chrono::Duration::microseconds(1);
+
+        // Each pass either returns or moves `local` forward to the next candidate; bounded to be safe with
+        // pathological specs like `*-02-30`.
+        for _ in 0..100_000 {
+            let candidate = self.next_local(local)?;
+            match tz.from_local_datetime(&candidate) {
+                LocalResult::Single(t) => return Some(t.with_timezone(&Utc)),
+                LocalResult::Ambiguous(earliest, _) => return Some(earliest.with_timezone(&Utc)),
+                // Skipped by a DST change; look again from the next minute.
+                LocalResult::None => local = candidate + chrono::Duration::minutes(1),
+            }
+        }
+        None
+    }
+
+    /// Next matching wall-clock time at or after `from`.
+    fn next_local(&self, from: NaiveDateTime) -> Option<NaiveDateTime> {
+        let (mut year, mut month, mut day) = (from.year() as u64, from.month() as u64, from.day() as u64);
+        let mut usec = (from.hour() as u64 * 3600 + from.minute() as u64 * 60 + from.second() as u64) * USEC_PER_SEC
+            + (from.nanosecond() as u64 / 1_000).min(USEC_PER_SEC - 1);
+
+        loop {
+            let next_year = self.year.next_match(year.max(MIN_YEAR), MAX_YEAR)?;
+            if next_year != year {
+                year = next_year;
+                month = 1;
+                day = 1;
+                usec = 0;
+            }
+
+            let next_month = match self.month.next_match(month, 12) {
+                Some(m) => m,
+                None => {
+                    year += 1;
+                    month = 1;
+                    day = 1;
+                    usec = 0;
+                    continue;
+                }
+            };
+            if next_month != month {
+                month = next_month;
+                day = 1;
+                usec = 0;
+            }
+
+            let days = days_in_month(year as i32, month as u32);
+            let day_chain = self.days_of_month(days);
+            let next_day = (day..=days).find(|d| self.day_matches(year, month, *d, &day_chain));
+            match next_day {
+                Some(d) if d != day => {
+                    day = d;
+                    usec = 0;
+                }
+                Some(_) => {}
+                None => {
+                    if month == 12 {
+                        year += 1;
+                        month = 1;
+                    } else {
+                        month += 1;
+                    }
+                    day = 1;
+                    usec = 0;
+                    continue;
+                }
+            }
+
+            match self.next_time(usec) {
+                Some(time) => {
+                    let date = NaiveDate::from_ymd_opt(year as i32, month as u32, day as u32)?;
+                    let seconds = time / USEC_PER_SEC;
+                    return date.and_hms_micro_opt(
+                        (seconds / 3600) as u32,
+                        (seconds / 60 % 60) as u32,
+                        (seconds % 60) as u32,
+                        (time % USEC_PER_SEC) as u32,
+                    );
+                }
+                None => {
+                    day += 1;
+                    usec = 0;
+                    if day > days {
+                        day = 1;
+                        if month == 12 {
+                            year += 1;
+                            month = 1;
+                        } else {
+                            month += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The day chain for a month of `days` days. With `~`, each start and stop counts back from the last day, like
+    /// systemd's find_end_of_month(), and repeats still run forward: `~07/1` is the last seven days.
+    fn days_of_month(&self, days: u64) -> Chain {
+        if !self.end_of_month {
+            return self.day.clone();
+        }
+        let from_end = |day: u64| (days + 1).checked_sub(day).filter(|day| *day > 0);
+        let components = self
+            .day
+            .0
+            .iter()
+            .filter_map(|component| {
+                let (start, stop) = match component.stop {
+                    // Ranges reaching past the start of a short month are cut at its first day.
+                    Some(stop) => {
+                        let (start, stop) = (from_end(component.start).unwrap_or(1), from_end(stop).unwrap_or(1));
+                        (start.min(stop), Some(start.max(stop)))
+                    }
+                    None => (from_end(component.start)?, None),
+                };
+                Some(Component {
+                    start,
+                    stop,
+                    repeat: component.repeat,
+                })
+            })
+            .collect();
+        Chain(components)
+    }
+
+    fn day_matches(&self, year: u64, month: u64, day: u64, day_chain: &Chain) -> bool {
+        if !day_chain.matches(day) {
+            return false;
+        }
+        match (
+            self.weekdays,
+            NaiveDate::from_ymd_opt(year as i32, month as u32, day as u32),
+        ) {
+            (Some(bits), Some(date)) => bits & (1 << date.weekday().num_days_from_monday()) != 0,
+            (None, Some(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Next matching time of day (in microseconds since midnight) at or after `usec`.
+    fn next_time(&self, usec: u64) -> Option<u64> {
+        let minute_usec = 60 * USEC_PER_SEC;
+        let (mut hour, mut minute, mut second) =
+            (usec / (60 * minute_usec), usec / minute_usec % 60, usec % minute_usec);
+        loop {
+            let next_hour = self.hour.next_match(hour, 23)?;
+            if next_hour != hour {
+                hour = next_hour;
+                minute = 0;
+                second = 0;
+            }
+            let next_minute = match self.minute.next_match(minute, 59) {
+                Some(m) => m,
+                None => {
+                    hour += 1;
+                    minute = 0;
+                    second = 0;
+                    continue;
+                }
+            };
+            if next_minute != minute {
+                minute = next_minute;
+                second = 0;
+            }
+            match self.second.next_match(second, minute_usec - 1) {
+                Some(s) => return Some(hour * 60 * minute_usec + minute * minute_usec + s),
+                None => {
+                    minute += 1;
+                    second = 0;
+                    if minute > 59 {
+                        minute = 0;
+                        hour += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for CalendarSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut words: Vec<&str> = s.split_whitespace().collect();
+        // Only a word naming a timezone is one; `*:0/15` and the like are part of the spec.
+        let timezone = match words.last() {
+            Some(&name) if words.len() > 1 && (name == "UTC" || name.parse::<Tz>().is_ok()) => Some(name.to_string()),
+            _ => None,
+        };
+        if timezone.is_some() {
+            words.pop();
+        }
+
+        if let [word] = words[..] {
+            if let Some((_, expanded)) = SHORTHANDS.iter().find(|(name, _)| name.eq_ignore_ascii_case(word)) {
+                let mut spec: CalendarSpec = expanded.parse()?;
+                spec.timezone = timezone;
+                return Ok(spec);
+            }
+        }
+
+        let mut words = words.into_iter().peekable();
+        let weekdays = match words.peek() {
+            Some(word) if word.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                Some(parse_weekdays(words.next().unwrap()).map_err(|e| anyhow!("invalid calendar spec {}: {}", s, e))?)
+            }
+            _ => None,
+        };
+        let rest: Vec<&str> = words.collect();
+        let (date, time) = match rest[..] {
+            [] if weekdays.is_none() => return Err(anyhow!("empty calendar spec")),
+            [] => (None, None),
+            [one] if one.contains(':') => (None, Some(one)),
+            [one] => (Some(one), None),
+            [date, time] => (Some(date), Some(time)),
+            _ => return Err(anyhow!("invalid calendar spec {}", s)),
+        };
+
+        let mut spec = CalendarSpec {
+            weekdays,
+            year: Chain::default(),
+            month: Chain::default(),
+            day: Chain::default(),
+            end_of_month: false,
+            hour: Chain(vec![fixed(0)]),
+            minute: Chain(vec![fixed(0)]),
+            second: Chain(vec![fixed(0)]),
+            timezone,
+        };
+        if let Some(date) = date {
+            parse_date(&mut spec, date).map_err(|e| anyhow!("invalid calendar spec {}: {}", s, e))?;
+        }
+        if let Some(time) = time {
+            parse_time(&mut spec, time).map_err(|e| anyhow!("invalid calendar spec {}: {}", s, e))?;
+        }
+        Ok(spec)
+    }
+}
+
+impl fmt::Display for CalendarSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(bits) = self.weekdays {
+            format_weekdays(f, bits)?;
+            f.write_str(" ")?;
+        }
+        self.year.format(f, 4, 1)?;
+        f.write_str("-")?;
+        self.month.format(f, 2, 1)?;
+        f.write_str(if self.end_of_month { "~" } else { "-" })?;
+        self.day.format(f, 2, 1)?;
+        f.write_str(" ")?;
+        self.hour.format(f, 2, 1)?;
+        f.write_str(":")?;
+        self.minute.format(f, 2, 1)?;
+        f.write_str(":")?;
+        self.second.format(f, 2, USEC_PER_SEC)?;
+        if let Some(timezone) = &self.timezone {
+            write!(f, " {}", timezone)?;
+        }
+        Ok(())
+    }
+}
+
+/// Serialized in its normalized `OnCalendar=` form.
+#[cfg(feature = "serde")]
+impl serde::Serialize for CalendarSpec {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for CalendarSpec {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn fixed(value: u64) -> Component {
+    Component {
+        start: value,
+        stop: None,
+        repeat: None,
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u64 {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    match (
+        NaiveDate::from_ymd_opt(year, month, 1),
+        NaiveDate::from_ymd_opt(next_year, next_month, 1),
+    ) {
+        (Some(first), Some(next)) => next.signed_duration_since(first).num_days() as u64,
+        _ => 31,
+    }
+}
+
+fn weekday(name: &str) -> Result<u8> {
+    let lower = name.to_ascii_lowercase();
+    WEEKDAY_NAMES
+        .iter()
+        .position(|day| lower.len() >= 3 && (**day == lower || day[..3] == lower))
+        .map(|i| i as u8)
+        .ok_or_else(|| anyhow!("unknown weekday {}", name))
+}
+
+fn parse_weekdays(s: &str) -> Result<u8> {
+    let mut bits = 0u8;
+    for item in s.split(',') {
+        let range = item
+            .find("..")
+            .map(|i| (&item[..i], &item[i + 2..]))
+            .or_else(|| item.find('-').map(|i| (&item[..i], &item[i + 1..])));
+        match range {
+            Some((from, to)) => {
+                let (from, to) = (weekday(from)?, weekday(to)?);
+                if from > to {
+                    return Err(anyhow!("weekday range {} is reversed", item));
+                }
+                for day in from..=to {
+                    bits |= 1 << day;
+                }
+            }
+            None => bits |= 1 << weekday(item)?,
+        }
+    }
+    Ok(bits)
+}
+
+fn format_weekdays(f: &mut fmt::Formatter<'_>, bits: u8) -> fmt::Result {
+    let mut runs: Vec<(usize, usize)> = vec![];
+    for day in 0..7 {
+        if bits & (1 << day) == 0 {
+            continue;
+        }
+        match runs.last_mut() {
+            Some((_, end)) if *end + 1 == day => *end = day,
+            _ => runs.push((day, day)),
+        }
+    }
+    let formatted: Vec<String> = runs
+        .into_iter()
+        .map(|(start, end)| match end - start {
+            0 => WEEKDAYS[start].to_string(),
+            1 => format!("{},{}", WEEKDAYS[start], WEEKDAYS[end]),
+            _ => format!("{}..{}", WEEKDAYS[start], WEEKDAYS[end]),
+        })
+        .collect();
+    f.write_str(&formatted.join(","))
+}
+
+fn parse_date(spec: &mut CalendarSpec, s: &str) -> Result<()> {
+    let (head, day) = match s.rfind(['-', '~']) {
+        Some(i) => {
+            spec.end_of_month = &s[i..=i] == "~";
+            (&s[..i], &s[i + 1..])
+        }
+        None => return Err(anyhow!("date {} needs a month and day", s)),
+    };
+    let (year, month) = match head.find('-') {
+        Some(i) => (Some(&head[..i]), &head[i + 1..]),
+        None => (None, head),
+    };
+
+    if let Some(year) = year {
+        spec.year = parse_chain(year, 1, MIN_YEAR, MAX_YEAR, true, false)?;
+    }
+    spec.month = parse_chain(month, 1, 1, 12, false, false)?;
+    // Days counted back from the end of the month are naturally written `~07..01`.
+    spec.day = parse_chain(day, 1, 1, 31, false, spec.end_of_month)?;
+    Ok(())
+}
+
+fn parse_time(spec: &mut CalendarSpec, s: &str) -> Result<()> {
+    let fields: Vec<&str> = s.split(':').collect();
+    let (hour, minute, second) = match fields[..] {
+        [hour, minute] => (hour, minute, None),
+        [hour, minute, second] => (hour, minute, Some(second)),
+        _ => return Err(anyhow!("time {} must be HH:MM or HH:MM:SS", s)),
+    };
+    spec.hour = parse_chain(hour, 1, 0, 23, false, false)?;
+    spec.minute = parse_chain(minute, 1, 0, 59, false, false)?;
+    spec.second = match second {
+        Some(second) => parse_chain(second, USEC_PER_SEC, 0, 60 * USEC_PER_SEC - 1, false, false)?,
+        None => Chain(vec![fixed(0)]),
+    };
+    Ok(())
+}
+
+/// Parses `*`, `a`, `a..b`, `a/r`, `a..b/r` and comma lists; values are multiplied by `scale`. Ranges running
+/// backwards are rejected unless `reversible`.
+fn parse_chain(s: &str, scale: u64, min: u64, max: u64, is_year: bool, reversible: bool) -> Result<Chain> {
+    if s == "*" {
+        return Ok(Chain::default());
+    }
+
+    let value = |v: &str| -> Result<u64> {
+        let parsed = match v.find('.') {
+            Some(dot) if scale > 1 => {
+                let whole: u64 = v[..dot].parse()?;
+                let fraction = &v[dot + 1..];
+                if fraction.is_empty() || fraction.len() > 6 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(anyhow!("invalid value {}", v));
+                }
+                whole * scale + fraction.parse::<u64>()? * 10u64.pow(6 - fraction.len() as u32)
+            }
+            _ => v.parse::<u64>().map_err(|_| anyhow!("invalid value {}", v))? * scale,
+        };
+        // Two-digit years are read the way strptime's %y does.
+        let parsed = match parsed {
+            y if is_year && y < 70 && v.len() <= 2 => y + 2000,
+            y if is_year && y < 100 && v.len() <= 2 => y + 1900,
+            other => other,
+        };
+        if parsed < min || parsed > max {
+            return Err(anyhow!("{} is out of range", v));
+        }
+        Ok(parsed)
+    };
+
+    let mut components = vec![];
+    for item in s.split(',') {
+        let (range, repeat) = match item.find('/') {
+            Some(i) => (&item[..i], Some(&item[i + 1..])),
+            None => (item, None),
+        };
+        let (start, stop) = match range.find("..") {
+            Some(i) => (&range[..i], Some(&range[i + 2..])),
+            None => (range, None),
+        };
+        let start = if start == "*" { min } else { value(start)? };
+        let stop = stop.map(value).transpose()?;
+        let repeat = match repeat {
+            Some(r) => {
+                let parsed = match r.find('.') {
+                    Some(_) if scale > 1 => value(r).map_err(|_| anyhow!("invalid repetition {}", r))?,
+                    _ => r.parse::<u64>().map_err(|_| anyhow!("invalid repetition {}", r))? * scale,
+                };
+                if parsed == 0 {
+                    return Err(anyhow!("repetition in {} must be positive", item));
+                }
+                Some(parsed)
+            }
+            None => None,
+        };
+        if let Some(stop) = stop {
+            if stop < start && !reversible {
+                return Err(anyhow!("range {} is reversed", item));
+            }
+        }
+        components.push(Component { start, stop, repeat });
+    }
+
+    components.sort();
+    components.dedup();
+    Ok(Chain(components))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::CalendarSpec;
+
+    fn normalize(s: &str) -> String {
+        s.parse::<CalendarSpec>().unwrap().to_string()
+    }
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(Utc.with_ymd_and_hms(y, mo, d, h, mi, s).unwrap().timestamp() as u64)
+    }
+
+    #[test]
+    fn normalizes_like_systemd_analyze_calendar() {
+        assert_eq!(normalize("Mon..Fri *-*-* 09:00:00"), "Mon..Fri *-*-* 09:00:00");
+        assert_eq!(normalize("weekly"), "Mon *-*-* 00:00:00");
+        assert_eq!(normalize("daily UTC"), "*-*-* 00:00:00 UTC");
+        assert_eq!(normalize("quarterly"), "*-01,04,07,10-01 00:00:00");
+        assert_eq!(normalize("Sat,Sun 12:05"), "Sat,Sun *-*-* 12:05:00");
+        assert_eq!(normalize("mon,tue,wed"), "Mon..Wed *-*-* 00:00:00");
+        assert_eq!(normalize("*:0/15"), "*-*-* *:00/15:00");
+        assert_eq!(normalize("*-*-* *:0/15"), "*-*-* *:00/15:00");
+        assert_eq!(normalize("Mon..Fri *-*-* 8/2:00"), "Mon..Fri *-*-* 08/2:00:00");
+        assert_eq!(normalize("Mon *-05~07/1"), "Mon *-05~07/1 00:00:00");
+        assert_eq!(normalize("*-*~07..01"), "*-*~07..01 00:00:00");
+        assert_eq!(normalize("daily GMT"), "*-*-* 00:00:00 GMT");
+        assert_eq!(normalize("12:00 EST5EDT"), "*-*-* 12:00:00 EST5EDT");
+        assert_eq!(normalize("2030-2-29"), "2030-02-29 00:00:00");
+        assert_eq!(normalize("*-02~01 23:59:59.5"), "*-02~01 23:59:59.500000");
+        assert_eq!(
+            normalize("Mon *-*-* 10:00 Europe/Berlin"),
+            "Mon *-*-* 10:00:00 Europe/Berlin"
+        );
+    }
+
+    #[test]
+    fn normalized_specs_parse_to_themselves() {
+        for spec in [
+            "*-*-* *:0/15",
+            "*:00/15:00",
+            "Mon..Fri *-*-* 8/2:00",
+            "Mon *-05~07/1",
+            "*-02~03..01 12:00 UTC",
+            "weekly Europe/Berlin",
+            "*-*-* 23:59:59.5",
+        ]
+        .iter()
+        {
+            let normalized = normalize(spec);
+            assert_eq!(normalize(&normalized), normalized, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_specs() {
+        assert!("".parse::<CalendarSpec>().is_err());
+        assert!("   ".parse::<CalendarSpec>().is_err());
+        assert!("Funday *-*-* 00:00".parse::<CalendarSpec>().is_err());
+        assert!("*-13-01".parse::<CalendarSpec>().is_err());
+        assert!("25:00".parse::<CalendarSpec>().is_err());
+        assert!("*:0/0".parse::<CalendarSpec>().is_err());
+        assert!("daily Mars/Olympus".parse::<CalendarSpec>().is_err());
+    }
+
+    #[test]
+    fn computes_next_elapse() {
+        let weekdays: CalendarSpec = "Mon..Fri *-*-* 09:00:00 UTC".parse().unwrap();
+        // 2024-03-08 is a Friday.
+        assert_eq!(
+            weekdays.next_elapse(at(2024, 3, 8, 9, 0, 0)),
+            Some(at(2024, 3, 11, 9, 0, 0))
+        );
+        assert_eq!(
+            weekdays.next_elapse(at(2024, 3, 8, 8, 59, 59)),
+            Some(at(2024, 3, 8, 9, 0, 0))
+        );
+
+        let last_day: CalendarSpec = "*-02~01 12:00 UTC".parse().unwrap();
+        assert_eq!(
+            last_day.next_elapse(at(2024, 1, 1, 0, 0, 0)),
+            Some(at(2024, 2, 29, 12, 0, 0))
+        );
+
+        // From systemd.time(7): the last Monday in May.
+        let last_monday: CalendarSpec = "Mon *-05~07/1 UTC".parse().unwrap();
+        assert_eq!(
+            last_monday.next_elapse(at(2026, 5, 1, 0, 0, 0)),
+            Some(at(2026, 5, 25, 0, 0, 0))
+        );
+        assert_eq!(
+            last_monday.next_elapse(at(2026, 5, 25, 0, 0, 0)),
+            Some(at(2027, 5, 31, 0, 0, 0))
+        );
+
+        let last_days: CalendarSpec = "*-02~03..01 12:00 UTC".parse().unwrap();
+        assert_eq!(
+            last_days.next_elapse(at(2024, 2, 1, 0, 0, 0)),
+            Some(at(2024, 2, 27, 12, 0, 0))
+        );
+
+        let quarter_hours: CalendarSpec = "*-*-* *:0/15 UTC".parse().unwrap();
+        assert_eq!(
+            quarter_hours.next_elapse(at(2024, 12, 31, 23, 50, 0)),
+            Some(at(2025, 1, 1, 0, 0, 0))
+        );
+
+        let never: CalendarSpec = "2030-02-30 UTC".parse().unwrap();
+        assert_eq!(never.next_elapse(at(2024, 1, 1, 0, 0, 0)), None);
+
+        // 09:00 in Berlin is 08:00 UTC in winter and 07:00 UTC in summer.
+        let berlin: CalendarSpec = "*-*-* 09:00 Europe/Berlin".parse().unwrap();
+        assert_eq!(
+            berlin.next_elapse(at(2024, 1, 15, 0, 0, 0)),
+            Some(at(2024, 1, 15, 8, 0, 0))
+        );
+        assert_eq!(
+            berlin.next_elapse(at(2024, 7, 15, 0, 0, 0)),
+            Some(at(2024, 7, 15, 7, 0, 0))
+        );
+    }
+}
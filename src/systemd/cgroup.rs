@@ -0,0 +1,230 @@
+use anyhow::{anyhow, Context, Result};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use super::{cgroup_interface, property_bool, property_str, SystemdManager};
+
+const DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Where the unified cgroup hierarchy is mounted; the default is /sys/fs/cgroup, tests point it at a temp dir.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CGroupFs {
+    root: PathBuf,
+}
+
+impl CGroupFs {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Directory of `cgroup`, a path like /system.slice/foo.service as systemd reports it in ControlGroup.
+    pub fn path(&self, cgroup: &str) -> PathBuf {
+        self.root.join(cgroup.trim_start_matches('/'))
+    }
+}
+
+impl Default for CGroupFs {
+    fn default() -> Self {
+        Self::new(DEFAULT_CGROUP_ROOT)
+    }
+}
+
+/// The cgroup of a unit with Delegate=yes, which the unit's own processes may organize into sub-cgroups.
+///
+/// Sub-cgroups are named relative to the unit's cgroup (`workers` or `workers/a`). cgroup v2 only allows
+/// processes in leaf cgroups once controllers are enabled for children, so a supervisor usually moves itself
+/// into a sub-cgroup of its own before starting workers in others.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DelegatedCGroup {
+    fs: CGroupFs,
+    control_group: String,
+}
+
+impl DelegatedCGroup {
+    pub fn new(fs: CGroupFs, control_group: &str) -> Self {
+        Self {
+            fs,
+            control_group: control_group.to_string(),
+        }
+    }
+
+    pub fn control_group(&self) -> &str {
+        &self.control_group
+    }
+
+    /// Directory of `subgroup`; "" is the unit's own cgroup.
+    pub fn path(&self, subgroup: &str) -> Result<PathBuf> {
+        check_subgroup(subgroup)?;
+        let path = self.fs.path(&self.control_group);
+        Ok(if subgroup.is_empty() { path } else { path.join(subgroup) })
+    }
+
+    /// Creates `subgroup` and any missing parents; creating one that exists is not an error.
+    pub fn create(&self, subgroup: &str) -> Result<PathBuf> {
+        let path = self.path(subgroup)?;
+        if subgroup.is_empty() {
+            return Err(anyhow!("the unit's own cgroup can't be created"));
+        }
+        fs::create_dir_all(&path).with_context(|| format!("creating {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Moves `pids` into `subgroup` by writing them to its cgroup.procs, one write per process as the kernel
+    /// requires.
+    pub fn attach(&self, subgroup: &str, pids: &[u32]) -> Result<()> {
+        let procs = self.path(subgroup)?.join("cgroup.procs");
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(&procs)
+            .with_context(|| format!("opening {}", procs.display()))?;
+        for pid in pids.iter() {
+            file.write_all(format!("{}\n", pid).as_bytes())
+                .with_context(|| format!("moving {} to {}", pid, procs.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Processes directly in `subgroup`, not in its children.
+    pub fn processes(&self, subgroup: &str) -> Result<Vec<u32>> {
+        let procs = self.path(subgroup)?.join("cgroup.procs");
+        let content = fs::read_to_string(&procs).with_context(|| format!("reading {}", procs.display()))?;
+        content
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                line.parse()
+                    .with_context(|| format!("bad pid {} in {}", line, procs.display()))
+            })
+            .collect()
+    }
+
+    /// Names of the direct sub-cgroups of `subgroup`.
+    pub fn subgroups(&self, subgroup: &str) -> Result<Vec<String>> {
+        let path = self.path(subgroup)?;
+        let mut names = vec![];
+        for entry in fs::read_dir(&path).with_context(|| format!("listing {}", path.display()))? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Removes an empty `subgroup`. Processes have to be moved out first; the kernel refuses otherwise, and so do
+    /// we, with a clearer error.
+    pub fn remove(&self, subgroup: &str) -> Result<()> {
+        if subgroup.is_empty() {
+            return Err(anyhow!("the unit's own cgroup can't be removed"));
+        }
+        let path = self.path(subgroup)?;
+        if path.join("cgroup.procs").exists() {
+            let remaining = self.processes(subgroup)?;
+            if !remaining.is_empty() {
+                return Err(anyhow!("{} still has processes {:?}", path.display(), remaining));
+            }
+        }
+        fs::remove_dir(&path).with_context(|| format!("removing {}", path.display()))
+    }
+}
+
+// Relative, no `.` or `..` components, and not named like the kernel's cgroup.* interface files.
+fn check_subgroup(subgroup: &str) -> Result<()> {
+    if subgroup.is_empty() {
+        return Ok(());
+    }
+    for component in subgroup.split('/') {
+        if component.is_empty() || component == "." || component == ".." || component.starts_with("cgroup.") {
+            return Err(anyhow!("invalid sub-cgroup name {:?}", subgroup));
+        }
+    }
+    Ok(())
+}
+
+impl SystemdManager {
+    /// The cgroup of `name`, which must be running with Delegate=yes, for managing its sub-cgroups under `fs`.
+    pub async fn delegated_cgroup(&self, name: &str, fs: CGroupFs) -> Result<DelegatedCGroup, dbus::MethodErr> {
+        let interface = match cgroup_interface(name) {
+            Some(interface) => interface,
+            None => return Err(dbus::MethodErr::invalid_arg(&format!("{} has no cgroup", name))),
+        };
+        let properties = self.get_unit_properties(name, interface).await?;
+        if property_bool(&properties, "Delegate") != Some(true) {
+            return Err(dbus::MethodErr::failed(&format!("{} does not have Delegate=yes", name)));
+        }
+        match property_str(&properties, "ControlGroup").filter(|cgroup| !cgroup.is_empty()) {
+            Some(control_group) => Ok(DelegatedCGroup::new(fs, control_group)),
+            None => Err(dbus::MethodErr::failed(&format!(
+                "{} has no cgroup; is it running?",
+                name
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{CGroupFs, DelegatedCGroup};
+
+    // A stand-in for cgroupfs: directories plus the cgroup.procs files the kernel would create.
+    fn fake_cgroupfs(test: &str) -> CGroupFs {
+        let root = std::env::temp_dir().join(format!("dbus-systemd-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("system.slice/supervisor.service")).unwrap();
+        fs::write(root.join("system.slice/supervisor.service/cgroup.procs"), "100\n").unwrap();
+        CGroupFs::new(root)
+    }
+
+    #[test]
+    fn manages_sub_cgroups() {
+        let fs_root = fake_cgroupfs("manages-sub-cgroups");
+        let cgroup = DelegatedCGroup::new(fs_root.clone(), "/system.slice/supervisor.service");
+
+        let workers = cgroup.create("workers/a").unwrap();
+        assert_eq!(
+            workers,
+            fs_root.root().join("system.slice/supervisor.service/workers/a")
+        );
+        fs::write(workers.join("cgroup.procs"), "").unwrap();
+
+        cgroup.attach("workers/a", &[200, 201]).unwrap();
+        assert_eq!(cgroup.processes("workers/a").unwrap(), vec![200, 201]);
+        assert_eq!(cgroup.processes("").unwrap(), vec![100]);
+        assert_eq!(cgroup.subgroups("").unwrap(), vec!["workers".to_string()]);
+        assert!(cgroup.remove("workers/a").is_err());
+
+        // Emulate the workers exiting; on cgroupfs rmdir ignores the interface files, a temp dir doesn't.
+        fs::remove_file(workers.join("cgroup.procs")).unwrap();
+        cgroup.remove("workers/a").unwrap();
+        assert!(cgroup.subgroups("workers").unwrap().is_empty());
+
+        fs::remove_dir_all(fs_root.root()).unwrap();
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_unit() {
+        let cgroup = DelegatedCGroup::new(CGroupFs::default(), "/system.slice/supervisor.service");
+        assert!(cgroup.path("../other.service").is_err());
+        assert!(cgroup.path("/abs").is_err());
+        assert!(cgroup.path("workers//a").is_err());
+        assert!(cgroup.path("cgroup.procs").is_err());
+        assert!(cgroup.path("init.scope").is_ok());
+        assert!(cgroup.remove("").is_err());
+        assert_eq!(
+            cgroup.path("workers").unwrap(),
+            std::path::Path::new("/sys/fs/cgroup/system.slice/supervisor.service/workers")
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    io,
+    os::unix::io::{AsFd, BorrowedFd},
+};
+
+use super::{Notifier, NotifyState, SystemdManager, SYSTEMD_SERVICE};
+
+// FDNAME_MAX in systemd.
+const MAX_FD_NAME: usize = 255;
+
+impl Notifier {
+    /// Hands copies of `fds` to the service manager to keep under `name` while the service restarts. They are
+    /// passed back on the next start like socket-activation sockets; see [`ListenFds::take_all`](super::ListenFds::take_all).
+    ///
+    /// The service needs FileDescriptorStoreMax= set high enough, or systemd closes them right away; see
+    /// [`SystemdManager::fd_store_max`].
+    pub fn store_fds<F: AsFd>(&self, name: &str, fds: &[F]) -> io::Result<()> {
+        check_fd_name(name)?;
+        let fds: Vec<BorrowedFd<'_>> = fds.iter().map(AsFd::as_fd).collect();
+        self.notify_with_fds(&[NotifyState::FdStore, NotifyState::FdName(name.to_string())], &fds)
+    }
+
+    /// Closes the descriptors stored under `name`.
+    pub fn remove_stored_fds(&self, name: &str) -> io::Result<()> {
+        check_fd_name(name)?;
+        self.notify(&[NotifyState::FdStoreRemove, NotifyState::FdName(name.to_string())])
+    }
+}
+
+/// [`Notifier::store_fds`] on `$NOTIFY_SOCKET`; `false` when the variable isn't set.
+pub fn store_fds<F: AsFd>(name: &str, fds: &[F]) -> io::Result<bool> {
+    match Notifier::from_env() {
+        Some(notifier) => notifier.store_fds(name, fds).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+/// [`Notifier::remove_stored_fds`] on `$NOTIFY_SOCKET`; `false` when the variable isn't set.
+pub fn remove_stored_fds(name: &str) -> io::Result<bool> {
+    match Notifier::from_env() {
+        Some(notifier) => notifier.remove_stored_fds(name).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+/// Names end up in LISTEN_FDNAMES, which is colon-separated.
+fn check_fd_name(name: &str) -> io::Result<()> {
+    let valid =
+        !name.is_empty() && name.len() <= MAX_FD_NAME && name.bytes().all(|b| (b' '..0x7f).contains(&b) && b != b':');
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid descriptor name {:?}", name),
+        ))
+    }
+}
+
+impl SystemdManager {
+    /// How many descriptors the service `name` may store (FileDescriptorStoreMax=); 0 means storing is off.
+    pub async fn fd_store_max(&self, name: &str) -> Result<u32, dbus::MethodErr> {
+        let properties = self.get_unit_properties(name, SYSTEMD_SERVICE.interface).await?;
+        properties
+            .get("FileDescriptorStoreMax")
+            .and_then(|v| v.0.as_u64())
+            .map(|max| max as u32)
+            .ok_or_else(|| dbus::MethodErr::invalid_arg(&format!("{} has no FileDescriptorStoreMax", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        io::{Read, Seek, SeekFrom, Write},
+    };
+
+    use super::check_fd_name;
+    use crate::{Notifier, NotifyListener};
+
+    #[test]
+    fn stores_and_removes_fds() {
+        let listener = NotifyListener::bind_abstract(&format!("dbus-systemd-fdstore-{}", std::process::id())).unwrap();
+        let notifier = Notifier::new(listener.socket());
+        let mut state = tempfile();
+        state.write_all(b"generation 7").unwrap();
+
+        notifier.store_fds("state", &[&state]).unwrap();
+        let (assignments, fds) = listener.recv_with_fds().unwrap();
+        assert_eq!(assignments, vec!["FDSTORE=1", "FDNAME=state"]);
+        assert_eq!(fds.len(), 1);
+        let mut stored = File::from(fds.into_iter().next().unwrap());
+        let mut contents = String::new();
+        stored.seek(SeekFrom::Start(0)).unwrap();
+        stored.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "generation 7");
+
+        notifier.remove_stored_fds("state").unwrap();
+        assert_eq!(listener.recv().unwrap(), vec!["FDSTOREREMOVE=1", "FDNAME=state"]);
+    }
+
+    #[test]
+    fn validates_fd_names() {
+        assert!(check_fd_name("conn-42").is_ok());
+        assert!(check_fd_name("").is_err());
+        assert!(check_fd_name("a:b").is_err());
+        assert!(check_fd_name("tab\there").is_err());
+        assert!(check_fd_name(&"x".repeat(256)).is_err());
+    }
+
+    fn tempfile() -> File {
+        let path = std::env::temp_dir().join(format!("dbus-systemd-fdstore-{}", std::process::id()));
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        file
+    }
+}
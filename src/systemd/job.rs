@@ -1,13 +1,25 @@
-use dbus::arg;
+use dbus::{arg, message::SignalArgs};
+use futures::{Future, StreamExt};
+use tracing::debug;
+
+use super::{Systemd1Manager, SystemdManager};
 
 pub type JobDto = (u32, String, String, String, dbus::Path<'static>, dbus::Path<'static>);
 
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Job {
+    #[cfg_attr(feature = "serde", serde(rename = "id"))]
     job_id: u32,
+    #[cfg_attr(feature = "serde", serde(rename = "unit"))]
     name: String,
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
     job_type: String,
+    #[cfg_attr(feature = "serde", serde(rename = "state"))]
     job_state: String,
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_path"))]
     job_path: dbus::Path<'static>,
+    #[cfg_attr(feature = "serde", serde(rename = "unit_path", with = "super::serde_path"))]
     path: dbus::Path<'static>,
 }
 
@@ -25,8 +37,10 @@ impl From<JobDto> for Job {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Systemd1ManagerJobNew {
     pub id: u32,
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_path"))]
     pub job_path: dbus::Path<'static>,
     pub unit: String,
 }
@@ -55,8 +69,10 @@ impl dbus::message::SignalArgs for Systemd1ManagerJobNew {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Systemd1ManagerJobRemoved {
     pub id: u32,
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_path"))]
     pub job_path: dbus::Path<'static>,
     pub unit: String,
     pub result: String,
@@ -86,3 +102,39 @@ impl dbus::message::SignalArgs for Systemd1ManagerJobRemoved {
     const NAME: &'static str = "JobRemoved";
     const INTERFACE: &'static str = "org.freedesktop.systemd1.Manager";
 }
+
+impl SystemdManager {
+    /// Runs a call that queues a job (start_unit, stop_unit, ...) and waits for the job to be removed.
+    ///
+    /// Returns the JobRemoved result: done, canceled, timeout, failed, dependency or skipped. The signal match is
+    /// added before `job` runs, so jobs finishing before the call returns aren't missed.
+    pub async fn await_job<F>(&self, job: F) -> Result<String, dbus::MethodErr>
+    where
+        F: Future<Output = Result<dbus::Path<'static>, dbus::MethodErr>> + Send,
+    {
+        let connection = self.connection().await?;
+        let rule = Systemd1ManagerJobRemoved::match_rule(None, None).static_clone();
+        let (removed_match, mut removed) = connection
+            .add_match(rule)
+            .await
+            .map_err(dbus::MethodErr::from)?
+            .stream::<Systemd1ManagerJobRemoved>();
+
+        let result = async {
+            self.subscribe().await?;
+            let job_path = job.await?;
+            while let Some((_, removed)) = removed.next().await {
+                if removed.job_path == job_path {
+                    return Ok(removed.result);
+                }
+            }
+            Err(dbus::MethodErr::failed(&"JobRemoved signal stream closed"))
+        }
+        .await;
+
+        if let Err(e) = connection.remove_match(removed_match.token()).await {
+            debug!("{:?}", e);
+        }
+        result
+    }
+}
@@ -0,0 +1,363 @@
+use ::systemd::journal::{self as sd_journal, Journal, JournalRecord};
+use chrono::{DateTime, Local};
+use futures::Stream;
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+
+use super::SystemdManager;
+
+/// Which boot to read logs from, like `journalctl -b`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Boot {
+    Current,
+    /// A boot ID as 32 hex characters, as found in `_BOOT_ID`.
+    Id(String),
+}
+
+/// Narrows the journal down the way `journalctl` flags do; the default is every entry.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogFilter {
+    /// Entries of any of these units, like repeated `journalctl -u`; empty means the whole journal.
+    pub units: Vec<String>,
+    /// Only entries of one run of the unit, matched against `_SYSTEMD_INVOCATION_ID` and `INVOCATION_ID`.
+    pub invocation_id: Option<String>,
+    pub boot: Option<Boot>,
+    /// Highest priority value to include (0 emerg ... 7 debug), like `journalctl -p`.
+    pub priority: Option<u8>,
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+    /// Only the most recent entries, like `journalctl -n`.
+    pub limit: Option<usize>,
+    /// Start right after the entry with this cursor, like `journalctl --after-cursor`.
+    pub after_cursor: Option<String>,
+}
+
+/// A journal entry with the commonly used fields pulled out; `fields` keeps everything, including those.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogEntry {
+    pub timestamp: SystemTime,
+    pub priority: Option<u8>,
+    pub message: String,
+    pub pid: Option<u32>,
+    /// Position in the journal; can be used to resume reading after this entry.
+    pub cursor: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl LogEntry {
+    pub fn from_fields(timestamp: SystemTime, cursor: String, fields: JournalRecord) -> Self {
+        Self {
+            timestamp,
+            priority: fields.get("PRIORITY").and_then(|p| p.parse().ok()),
+            message: fields.get("MESSAGE").cloned().unwrap_or_default(),
+            pid: fields.get("_PID").and_then(|p| p.parse().ok()),
+            cursor,
+            fields,
+        }
+    }
+}
+
+impl fmt::Display for LogEntry {
+    /// One line in journalctl's default `short` format: `Jan 04 10:00:00 host nginx[1234]: message`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            DateTime::<Local>::from(self.timestamp).format("%b %d %H:%M:%S")
+        )?;
+        if let Some(hostname) = self.fields.get("_HOSTNAME") {
+            write!(f, " {}", hostname)?;
+        }
+        let identifier = self
+            .fields
+            .get("SYSLOG_IDENTIFIER")
+            .or_else(|| self.fields.get("_COMM"))
+            .map(String::as_str)
+            .unwrap_or("unknown");
+        write!(f, " {}", identifier)?;
+        if let Some(pid) = self.fields.get("SYSLOG_PID").or_else(|| self.fields.get("_PID")) {
+            write!(f, "[{}]", pid)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl SystemdManager {
+    /// Reads the journal entries of `unit`, oldest first, like `journalctl -u`.
+    pub async fn logs(&self, unit: &str, mut filter: LogFilter) -> Result<Vec<LogEntry>, dbus::MethodErr> {
+        filter.units = vec![unit.to_string()];
+        match tokio::task::spawn_blocking(move || read_logs(&filter)).await {
+            Ok(Ok(entries)) => Ok(entries),
+            Ok(Err(e)) => Err(dbus::MethodErr::failed(&e)),
+            Err(e) => Err(dbus::MethodErr::failed(&e)),
+        }
+    }
+
+    /// Streams new journal entries as they are written, like `journalctl -f`.
+    ///
+    /// Without `filter.after_cursor` the stream starts at the tail, after replaying the last `filter.limit`
+    /// entries; with it, reading resumes right after that entry, so a consumer that stores the cursor of the
+    /// last entry it handled picks up exactly where it stopped. The stream ends after `filter.until`, on a
+    /// journal error, or when it is dropped.
+    pub fn follow_logs(&self, filter: LogFilter) -> impl Stream<Item = Result<LogEntry, dbus::MethodErr>> + Send {
+        let (sender, receiver) = mpsc::channel(FOLLOW_BUFFER);
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = follow(&filter, &sender) {
+                let _ = sender.blocking_send(Err(dbus::MethodErr::failed(&e)));
+            }
+        });
+        futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|entry| (entry, receiver))
+        })
+    }
+}
+
+const FOLLOW_BUFFER: usize = 256;
+// How often the blocking reader wakes up to notice a dropped stream when the journal is quiet.
+const FOLLOW_WAIT: Duration = Duration::from_millis(500);
+
+/// Opens the local journal with the matches for `filter` applied.
+pub(crate) fn open_journal(filter: &LogFilter) -> io::Result<Journal> {
+    let mut journal = sd_journal::OpenOptions::default().local_only(true).open()?;
+    for (i, unit) in filter.units.iter().enumerate() {
+        if i > 0 {
+            journal.match_or()?;
+        }
+        add_unit_matches(&mut journal, unit)?;
+    }
+    if let Some(priority) = filter.priority {
+        journal.match_and()?;
+        for level in 0..=priority.min(7) {
+            journal.match_add("PRIORITY", level.to_string())?;
+        }
+    }
+    if let Some(boot) = &filter.boot {
+        let boot_id = match boot {
+            Boot::Current => ::systemd::id128::Id128::from_boot()?.to_string(),
+            Boot::Id(id) => id.clone(),
+        };
+        journal.match_and()?;
+        journal.match_add("_BOOT_ID", boot_id)?;
+    }
+    if let Some(invocation_id) = &filter.invocation_id {
+        journal.match_and()?;
+        journal.match_add("_SYSTEMD_INVOCATION_ID", invocation_id.as_str())?;
+        journal.match_or()?;
+        journal.match_add("INVOCATION_ID", invocation_id.as_str())?;
+    }
+    Ok(journal)
+}
+
+// Same matches as journalctl -u: the unit's own output plus what PID 1 and the manager log about it.
+fn add_unit_matches(journal: &mut Journal, unit: &str) -> io::Result<()> {
+    journal.match_add("_SYSTEMD_UNIT", unit)?;
+    journal.match_or()?;
+    journal.match_add("_PID", "1")?.match_add("UNIT", unit)?;
+    journal.match_or()?;
+    journal.match_add("_UID", "0")?.match_add("OBJECT_SYSTEMD_UNIT", unit)?;
+    Ok(())
+}
+
+pub(crate) fn current_entry(journal: &mut Journal, fields: JournalRecord) -> io::Result<LogEntry> {
+    Ok(LogEntry::from_fields(journal.timestamp()?, journal.cursor()?, fields))
+}
+
+fn read_logs(filter: &LogFilter) -> io::Result<Vec<LogEntry>> {
+    let mut journal = open_journal(filter)?;
+    let mut entries = vec![];
+
+    if let Some(cursor) = &filter.after_cursor {
+        seek_after_cursor(&mut journal, cursor, &mut entries)?;
+        while let Some(fields) = journal.next_entry()? {
+            let entry = current_entry(&mut journal, fields)?;
+            if filter.until.is_some_and(|until| entry.timestamp > until) {
+                break;
+            }
+            entries.push(entry);
+        }
+        if let Some(limit) = filter.limit {
+            entries.drain(..entries.len().saturating_sub(limit));
+        }
+        return Ok(entries);
+    }
+
+    match filter.limit {
+        // Walk back from the tail so only the last entries are copied.
+        Some(limit) => {
+            match filter.until {
+                Some(until) => journal.seek_realtime_usec(realtime_usec(until))?,
+                None => journal.seek_tail()?,
+            }
+            while entries.len() < limit {
+                let fields = match journal.previous_entry()? {
+                    Some(fields) => fields,
+                    None => break,
+                };
+                let entry = current_entry(&mut journal, fields)?;
+                if filter.since.is_some_and(|since| entry.timestamp < since) {
+                    break;
+                }
+                if filter.until.is_some_and(|until| entry.timestamp > until) {
+                    continue;
+                }
+                entries.push(entry);
+            }
+            entries.reverse();
+        }
+        None => {
+            match filter.since {
+                Some(since) => journal.seek_realtime_usec(realtime_usec(since))?,
+                None => journal.seek_head()?,
+            }
+            while let Some(fields) = journal.next_entry()? {
+                let entry = current_entry(&mut journal, fields)?;
+                if filter.since.is_some_and(|since| entry.timestamp < since) {
+                    continue;
+                }
+                if filter.until.is_some_and(|until| entry.timestamp > until) {
+                    break;
+                }
+                entries.push(entry);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn follow(filter: &LogFilter, sender: &mpsc::Sender<Result<LogEntry, dbus::MethodErr>>) -> io::Result<()> {
+    let mut journal = open_journal(filter)?;
+    let mut pending = vec![];
+    match follow_start(filter) {
+        FollowStart::AfterCursor(cursor) => seek_after_cursor(&mut journal, cursor, &mut pending)?,
+        FollowStart::Since(usec) => journal.seek_realtime_usec(usec)?,
+        FollowStart::Tail(back) => {
+            // Step back over the entries to replay; the next read then returns the first of them.
+            journal.seek_tail()?;
+            if (journal.previous_skip(back)? as u64) < back {
+                journal.seek_head()?;
+            }
+        }
+    }
+
+    loop {
+        let entry = match pending.pop() {
+            Some(entry) => entry,
+            None => match journal.next_entry()? {
+                Some(fields) => current_entry(&mut journal, fields)?,
+                None => {
+                    if sender.is_closed() {
+                        return Ok(());
+                    }
+                    journal.wait(Some(FOLLOW_WAIT))?;
+                    continue;
+                }
+            },
+        };
+        if filter.since.is_some_and(|since| entry.timestamp < since) {
+            continue;
+        }
+        if filter.until.is_some_and(|until| entry.timestamp > until) {
+            return Ok(());
+        }
+        if sender.blocking_send(Ok(entry)).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum FollowStart<'a> {
+    AfterCursor(&'a str),
+    /// Realtime microseconds to seek to.
+    Since(u64),
+    /// Entries to step back from the tail: the replayed ones plus one.
+    Tail(u64),
+}
+
+// A cursor takes precedence over `since`, like journalctl; without either, following starts at the tail after
+// replaying `limit` entries.
+fn follow_start(filter: &LogFilter) -> FollowStart<'_> {
+    match (&filter.after_cursor, filter.since) {
+        (Some(cursor), _) => FollowStart::AfterCursor(cursor),
+        (None, Some(since)) => FollowStart::Since(realtime_usec(since)),
+        (None, None) => FollowStart::Tail(filter.limit.unwrap_or(0) as u64 + 1),
+    }
+}
+
+// Positions the journal so the next read returns the entry after `cursor`. If that entry is gone (rotated or
+// vacuumed), seeking lands on the closest later one instead, which is pushed to `pending` so it isn't lost.
+fn seek_after_cursor(journal: &mut Journal, cursor: &str, pending: &mut Vec<LogEntry>) -> io::Result<()> {
+    journal.seek_cursor(cursor)?;
+    if let Some(fields) = journal.next_entry()? {
+        if !journal.test_cursor(cursor)? {
+            pending.push(current_entry(journal, fields)?);
+        }
+    }
+    Ok(())
+}
+
+fn realtime_usec(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{follow_start, FollowStart, LogEntry, LogFilter};
+
+    #[test]
+    fn follows_from_cursor_then_since_then_tail() {
+        let since = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let resumed = LogFilter {
+            after_cursor: Some("s=0;i=1".to_string()),
+            since: Some(since),
+            limit: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(follow_start(&resumed), FollowStart::AfterCursor("s=0;i=1"));
+
+        let recent = LogFilter {
+            since: Some(since),
+            ..Default::default()
+        };
+        assert_eq!(follow_start(&recent), FollowStart::Since(1_600_000_000_000_000));
+
+        let replay = LogFilter {
+            limit: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(follow_start(&replay), FollowStart::Tail(11));
+        assert_eq!(follow_start(&LogFilter::default()), FollowStart::Tail(1));
+    }
+
+    #[test]
+    fn extracts_common_fields() {
+        let fields = [
+            ("MESSAGE", "Started Batch job."),
+            ("PRIORITY", "6"),
+            ("_PID", "1"),
+            ("_SYSTEMD_UNIT", "init.scope"),
+            ("UNIT", "batch.service"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let timestamp = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let entry = LogEntry::from_fields(timestamp, "s=0;i=1".to_string(), fields);
+
+        assert_eq!(entry.message, "Started Batch job.");
+        assert_eq!(entry.priority, Some(6));
+        assert_eq!(entry.pid, Some(1));
+        assert_eq!(entry.fields["UNIT"], "batch.service");
+    }
+}
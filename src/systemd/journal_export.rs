@@ -0,0 +1,327 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::{
+    convert::TryFrom,
+    io::{BufRead, Write},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use super::LogEntry;
+
+/// A journal entry as it appears in the export and JSON formats: every field in order, repeated fields kept,
+/// and values as raw bytes since journal fields need not be text.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JournalExportEntry {
+    pub fields: Vec<(String, Vec<u8>)>,
+}
+
+impl JournalExportEntry {
+    pub fn push<V: Into<Vec<u8>>>(&mut self, name: &str, value: V) {
+        self.fields.push((name.to_string(), value.into()));
+    }
+
+    /// First value of `name`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.get(name).and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    /// Converts back to a `LogEntry`; needs `__REALTIME_TIMESTAMP`. Values that aren't UTF-8 are converted
+    /// lossily and only the last value of a repeated field is kept.
+    pub fn to_log_entry(&self) -> Result<LogEntry> {
+        let realtime: u64 = self
+            .get_str("__REALTIME_TIMESTAMP")
+            .ok_or_else(|| anyhow!("entry has no __REALTIME_TIMESTAMP"))?
+            .parse()?;
+        let fields = self
+            .fields
+            .iter()
+            .filter(|(name, _)| !name.starts_with("__"))
+            .map(|(name, value)| (name.clone(), String::from_utf8_lossy(value).into_owned()))
+            .collect();
+        Ok(LogEntry::from_fields(
+            UNIX_EPOCH + Duration::from_micros(realtime),
+            self.get_str("__CURSOR").unwrap_or_default().to_string(),
+            fields,
+        ))
+    }
+}
+
+impl From<&LogEntry> for JournalExportEntry {
+    fn from(log_entry: &LogEntry) -> Self {
+        let realtime = log_entry
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        let mut entry = JournalExportEntry::default();
+        if !log_entry.cursor.is_empty() {
+            entry.push("__CURSOR", log_entry.cursor.as_str());
+        }
+        entry.push("__REALTIME_TIMESTAMP", realtime.to_string());
+        for (name, value) in log_entry.fields.iter() {
+            entry.push(name, value.as_str());
+        }
+        entry
+    }
+}
+
+/// Writes entries in the Journal Export Format, as `journalctl -o export` does and `systemd-journal-remote`
+/// reads. Values that aren't printable on one line are written in the binary form.
+pub fn write_export<W: Write>(mut writer: W, entries: &[JournalExportEntry]) -> Result<()> {
+    for entry in entries.iter() {
+        for (name, value) in entry.fields.iter() {
+            check_field_name(name)?;
+            if is_printable(value, false) {
+                writer.write_all(name.as_bytes())?;
+                writer.write_all(b"=")?;
+                writer.write_all(value)?;
+            } else {
+                writer.write_all(name.as_bytes())?;
+                writer.write_all(b"\n")?;
+                writer.write_all(&(value.len() as u64).to_le_bytes())?;
+                writer.write_all(value)?;
+            }
+            writer.write_all(b"\n")?;
+        }
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Reads entries in the Journal Export Format.
+pub fn read_export<R: BufRead>(mut reader: R) -> Result<Vec<JournalExportEntry>> {
+    let mut entries = vec![];
+    let mut entry = JournalExportEntry::default();
+    let mut line = vec![];
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+
+        if line.is_empty() {
+            if !entry.fields.is_empty() {
+                entries.push(std::mem::take(&mut entry));
+            }
+            continue;
+        }
+        match line.iter().position(|b| *b == b'=') {
+            Some(eq) => {
+                let name = String::from_utf8(line[..eq].to_vec())?;
+                check_field_name(&name)?;
+                entry.push(&name, &line[eq + 1..]);
+            }
+            None => {
+                let name = String::from_utf8(line.clone())?;
+                check_field_name(&name)?;
+                let mut size = [0u8; 8];
+                reader.read_exact(&mut size)?;
+                let size = usize::try_from(u64::from_le_bytes(size))?;
+                let mut value = vec![0u8; size];
+                reader.read_exact(&mut value)?;
+                let mut newline = [0u8; 1];
+                reader.read_exact(&mut newline)?;
+                if newline[0] != b'\n' {
+                    return Err(anyhow!("binary field {} is not terminated by a newline", name));
+                }
+                entry.push(&name, value);
+            }
+        }
+    }
+    if !entry.fields.is_empty() {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Writes entries one JSON object per line like `journalctl -o json`: values are strings, values that aren't
+/// printable UTF-8 are arrays of byte values, and a repeated field is an array of its values.
+pub fn write_json<W: Write>(mut writer: W, entries: &[JournalExportEntry]) -> Result<()> {
+    for entry in entries.iter() {
+        let mut names: Vec<&str> = vec![];
+        for (name, _) in entry.fields.iter() {
+            check_field_name(name)?;
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+
+        let mut object = vec![];
+        for name in names {
+            let mut values: Vec<Value> = entry
+                .fields
+                .iter()
+                .filter(|(field, _)| field == name)
+                .map(|(_, value)| json_value(value))
+                .collect();
+            let value = if values.len() == 1 {
+                values.remove(0)
+            } else {
+                Value::Array(values)
+            };
+            object.push(format!("{}:{}", Value::from(name), value));
+        }
+        writeln!(writer, "{{{}}}", object.join(","))?;
+    }
+    Ok(())
+}
+
+/// Reads entries written one JSON object per line, as by `journalctl -o json`. Fields of an object are put in
+/// name order; `null` values (fields journalctl left out for size) are skipped.
+pub fn read_json<R: BufRead>(reader: R) -> Result<Vec<JournalExportEntry>> {
+    let mut entries = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let object = match serde_json::from_str(&line)? {
+            Value::Object(object) => object,
+            other => return Err(anyhow!("expected a JSON object per line, got {}", other)),
+        };
+        let mut entry = JournalExportEntry::default();
+        for (name, value) in object {
+            check_field_name(&name)?;
+            match value {
+                Value::Null => {}
+                Value::Array(values) if values.iter().all(|v| v.is_string() || v.is_array()) => {
+                    for value in values.iter() {
+                        entry.push(&name, json_bytes(&name, value)?);
+                    }
+                }
+                value => entry.push(&name, json_bytes(&name, &value)?),
+            }
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn json_value(value: &[u8]) -> Value {
+    if is_printable(value, true) {
+        Value::from(String::from_utf8_lossy(value).into_owned())
+    } else {
+        Value::from(value.to_vec())
+    }
+}
+
+fn json_bytes(name: &str, value: &Value) -> Result<Vec<u8>> {
+    match value {
+        Value::String(value) => Ok(value.as_bytes().to_vec()),
+        Value::Array(bytes) => bytes
+            .iter()
+            .map(|b| {
+                b.as_u64()
+                    .and_then(|b| u8::try_from(b).ok())
+                    .ok_or_else(|| anyhow!("field {} has a byte array with {} in it", name, b))
+            })
+            .collect(),
+        other => Err(anyhow!("field {} has unsupported value {}", name, other)),
+    }
+}
+
+// Same rule as systemd's utf8_is_printable_newline(): valid UTF-8 without control characters except tab,
+// and newline only where the format allows it.
+fn is_printable(value: &[u8], newline: bool) -> bool {
+    match std::str::from_utf8(value) {
+        Ok(value) => value.chars().all(|c| {
+            let control = (c < ' ' && c != '\t' && c != '\n') || ('\u{7f}'..='\u{9f}').contains(&c);
+            !control && (newline || c != '\n')
+        }),
+        Err(_) => false,
+    }
+}
+
+// Field names in both formats: uppercase letters, digits and underscores, not starting with a digit.
+fn check_field_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("invalid journal field name {:?}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{read_export, read_json, write_export, write_json, JournalExportEntry};
+
+    const EXPORT: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/journal.export"));
+    const JSON: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/journal.json"));
+
+    fn sorted(mut entries: Vec<JournalExportEntry>) -> Vec<JournalExportEntry> {
+        for entry in entries.iter_mut() {
+            entry.fields.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        entries
+    }
+
+    #[test]
+    fn export_format_round_trips() {
+        let entries = read_export(EXPORT).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].get("MESSAGE").unwrap(), b"Traceback:\n  line 1");
+        assert_eq!(entries[2].get("PAYLOAD").unwrap(), &[0x00, 0xff, 0x10, 0x0a]);
+        assert_eq!(entries[2].fields.iter().filter(|(name, _)| name == "TAG").count(), 2);
+
+        let mut written = vec![];
+        write_export(&mut written, &entries).unwrap();
+        assert_eq!(written, EXPORT);
+    }
+
+    #[test]
+    fn json_matches_journalctl() {
+        let entries = read_export(EXPORT).unwrap();
+        let mut written = vec![];
+        write_json(&mut written, &entries).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            String::from_utf8(JSON.to_vec()).unwrap()
+        );
+
+        assert_eq!(read_json(JSON).unwrap(), sorted(entries));
+    }
+
+    #[test]
+    fn converts_to_log_entries() {
+        let entries = read_export(EXPORT).unwrap();
+        let log_entry = entries[0].to_log_entry().unwrap();
+        assert_eq!(log_entry.message, "Started Batch job.");
+        assert_eq!(log_entry.priority, Some(6));
+        assert_eq!(
+            log_entry.timestamp,
+            UNIX_EPOCH + Duration::from_micros(1_600_000_000_000_001)
+        );
+        assert!(!log_entry.fields.contains_key("__CURSOR"));
+
+        let back = JournalExportEntry::from(&log_entry);
+        assert_eq!(back.get_str("__CURSOR"), entries[0].get_str("__CURSOR"));
+        assert_eq!(back.get_str("__REALTIME_TIMESTAMP"), Some("1600000000000001"));
+        assert_eq!(back.get_str("MESSAGE"), Some("Started Batch job."));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(read_export(&b"lower=case\n"[..]).is_err());
+        assert!(read_export(&b"MESSAGE\n\x05\x00\x00\x00\x00\x00\x00\x00ab"[..]).is_err());
+        assert!(read_json(&b"[1, 2]\n"[..]).is_err());
+        assert!(read_json(&b"{\"PAYLOAD\":[256]}\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,268 @@
+use ::systemd::journal;
+use std::fmt;
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+// journald drops fields with longer names.
+const MAX_FIELD_NAME: usize = 64;
+// Keeps event and span fields from overwriting the ones the layer writes itself, as tracing-journald does.
+const FIELD_PREFIX: &str = "F_";
+
+/// A tracing layer that sends events to journald as structured entries.
+///
+/// Each event becomes one journal entry: the message as MESSAGE, the level as PRIORITY, the event's fields and
+/// those of every span it is in as journal fields (names prefixed with `F_`, uppercased and sanitized, outermost
+/// span first), plus CODE_FILE, CODE_LINE, TARGET and SYSLOG_IDENTIFIER. A `priority` field thus becomes
+/// F_PRIORITY and can't override the level.
+pub struct JournalLayer {
+    syslog_identifier: String,
+    send: fn(&[&str]),
+}
+
+impl JournalLayer {
+    /// Uses the executable's file name as SYSLOG_IDENTIFIER, like journald does for stdout.
+    pub fn new() -> Self {
+        let syslog_identifier = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.file_name().map(|name| name.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        Self {
+            syslog_identifier,
+            send: send_to_journal,
+        }
+    }
+
+    pub fn with_syslog_identifier(mut self, syslog_identifier: &str) -> Self {
+        self.syslog_identifier = syslog_identifier.to_string();
+        self
+    }
+
+    fn entry<S>(&self, event: &Event<'_>, ctx: &Context<'_, S>) -> Vec<String>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let metadata = event.metadata();
+        let mut fields = vec![format!("PRIORITY={}", priority(metadata.level()))];
+        if !self.syslog_identifier.is_empty() {
+            fields.push(format!("SYSLOG_IDENTIFIER={}", self.syslog_identifier));
+        }
+        fields.push(format!("TARGET={}", metadata.target()));
+        if let Some(file) = metadata.file() {
+            fields.push(format!("CODE_FILE={}", file));
+        }
+        if let Some(line) = metadata.line() {
+            fields.push(format!("CODE_LINE={}", line));
+        }
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(span_fields) = span.extensions().get::<SpanFields>() {
+                    fields.extend(span_fields.0.iter().cloned());
+                }
+            }
+        }
+
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        fields.push(format!("MESSAGE={}", visitor.message.unwrap_or_default()));
+        fields.extend(visitor.fields);
+        fields
+    }
+}
+
+impl Default for JournalLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Layer<S> for JournalLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        span.extensions_mut().insert(SpanFields(visitor.fields));
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut visitor = FieldVisitor::default();
+        values.record(&mut visitor);
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<SpanFields>() {
+            Some(span_fields) => span_fields.0.extend(visitor.fields),
+            None => extensions.insert(SpanFields(visitor.fields)),
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let entry = self.entry(event, &ctx);
+        let entry: Vec<&str> = entry.iter().map(String::as_str).collect();
+        (self.send)(&entry);
+    }
+}
+
+fn send_to_journal(fields: &[&str]) {
+    // There is nowhere to report a failure to log; journald being down must not take the service with it.
+    let _ = journal::send(fields);
+}
+
+// Syslog levels; there is no notice in tracing, so info maps to 6 like the log crate integration does.
+fn priority(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Turns a tracing field name into a valid journal field name: uppercase ASCII letters, digits and `_`, not
+/// starting with `_` (reserved for trusted fields) or a digit, and at most 64 characters.
+pub fn journal_field_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' => c.to_ascii_uppercase(),
+            'A'..='Z' | '0'..='9' => c,
+            _ => '_',
+        })
+        .collect::<String>()
+        .trim_start_matches('_')
+        .to_string();
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, 'F');
+    }
+    sanitized.truncate(MAX_FIELD_NAME);
+    sanitized
+}
+
+struct SpanFields(Vec<String>);
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: Option<String>,
+    fields: Vec<String>,
+}
+
+impl FieldVisitor {
+    fn push(&mut self, field: &Field, value: String) {
+        if field.name() == "message" {
+            self.message = Some(value);
+            return;
+        }
+        let name = journal_field_name(&format!("{}{}", FIELD_PREFIX, field.name()));
+        self.fields.push(format!("{}={}", name, value));
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(field, format!("{:?}", value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, thread_local};
+    use tracing::{info, info_span, warn};
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    use super::{journal_field_name, JournalLayer};
+
+    thread_local! {
+        static SENT: RefCell<Vec<Vec<String>>> = const { RefCell::new(vec![]) };
+    }
+
+    fn capture(fields: &[&str]) {
+        SENT.with(|sent| sent.borrow_mut().push(fields.iter().map(|f| f.to_string()).collect()));
+    }
+
+    #[test]
+    fn sanitizes_field_names() {
+        assert_eq!(journal_field_name("request_id"), "REQUEST_ID");
+        assert_eq!(journal_field_name("http.method"), "HTTP_METHOD");
+        assert_eq!(journal_field_name("_pid"), "PID");
+        assert_eq!(journal_field_name("2fa"), "F2FA");
+        assert_eq!(journal_field_name(&"x".repeat(80)).len(), 64);
+    }
+
+    #[test]
+    fn sends_structured_entries() {
+        let layer = JournalLayer {
+            syslog_identifier: "batch".to_string(),
+            send: capture,
+        };
+        let subscriber = Registry::default().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!("request", request_id = 7, path = "/units");
+            let _entered = span.enter();
+            info!(unit = "batch.service", "started {}", "batch");
+            warn!("slow");
+        });
+
+        let sent = SENT.with(|sent| sent.borrow().clone());
+        assert_eq!(sent.len(), 2);
+        let started = &sent[0];
+        for expected in [
+            "PRIORITY=6",
+            "SYSLOG_IDENTIFIER=batch",
+            "MESSAGE=started batch",
+            "F_UNIT=batch.service",
+            "F_REQUEST_ID=7",
+            "F_PATH=/units",
+        ]
+        .iter()
+        {
+            assert!(
+                started.iter().any(|f| f == expected),
+                "{} missing from {:?}",
+                expected,
+                started
+            );
+        }
+        assert!(started.iter().any(|f| f.starts_with("CODE_FILE=")));
+        assert!(started.iter().any(|f| f.starts_with("CODE_LINE=")));
+        assert!(sent[1].iter().any(|f| f == "PRIORITY=4"));
+    }
+
+    #[test]
+    fn event_fields_cannot_override_layer_fields() {
+        let layer = JournalLayer {
+            syslog_identifier: "batch".to_string(),
+            send: capture,
+        };
+        let subscriber = Registry::default().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            info!(priority = 0, syslog_identifier = "spoofed", "done");
+        });
+
+        let sent = SENT.with(|sent| sent.borrow().clone());
+        let done = &sent[0];
+        let named = |prefix: &str| {
+            done.iter()
+                .filter(|f| f.starts_with(prefix))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(named("PRIORITY="), vec!["PRIORITY=6"]);
+        assert_eq!(named("SYSLOG_IDENTIFIER="), vec!["SYSLOG_IDENTIFIER=batch"]);
+        assert!(done.iter().any(|f| f == "F_PRIORITY=0"));
+        assert!(done.iter().any(|f| f == "F_SYSLOG_IDENTIFIER=spoofed"));
+    }
+}
@@ -0,0 +1,662 @@
+use dbus::{arg, message::SignalArgs};
+use futures::StreamExt;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+use tracing::debug;
+
+use crate::dbus::DBusPropertiesPropertiesChanged;
+
+use super::{
+    cgroup_interface, property_i64, property_str, property_timestamp, unit_name_from_object_path, Systemd1Manager,
+    Systemd1ManagerJobNew, Systemd1ManagerJobRemoved, Systemd1ManagerUnitNew, Systemd1ManagerUnitRemoved,
+    SystemdManager, SystemdUnitStatus, UsageSample, SYSTEMD, SYSTEMD_UNIT,
+};
+
+const ACTIVE_STATES: &[&str] = &[
+    "active",
+    "reloading",
+    "inactive",
+    "failed",
+    "activating",
+    "deactivating",
+    "maintenance",
+    "refreshing",
+];
+// Manager properties with the realtime of each boot stage, and the stage label they're exported under.
+const BOOT_STAGES: &[(&str, &str)] = &[
+    ("firmware", "FirmwareTimestamp"),
+    ("loader", "LoaderTimestamp"),
+    ("kernel", "KernelTimestamp"),
+    ("initrd", "InitRDTimestamp"),
+    ("userspace", "UserspaceTimestamp"),
+    ("finish", "FinishTimestamp"),
+];
+
+// Accounting families: name, type, help, the counter of a UsageSample they export and what to divide it by to
+// get the family's unit.
+type UsageFamily = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&UsageSample) -> Option<u64>,
+    f64,
+);
+const USAGE_FAMILIES: &[UsageFamily] = &[
+    (
+        "systemd_unit_cpu_seconds_total",
+        "counter",
+        "CPU time used by the unit.",
+        |u| u.cpu_usage_nsec,
+        1e9,
+    ),
+    (
+        "systemd_unit_memory_bytes",
+        "gauge",
+        "Memory used by the unit's cgroup.",
+        |u| u.memory_current,
+        1.0,
+    ),
+    (
+        "systemd_unit_tasks_current",
+        "gauge",
+        "Tasks in the unit's cgroup.",
+        |u| u.tasks_current,
+        1.0,
+    ),
+    (
+        "systemd_unit_io_read_bytes_total",
+        "counter",
+        "Bytes read by the unit.",
+        |u| u.io_read_bytes,
+        1.0,
+    ),
+    (
+        "systemd_unit_io_write_bytes_total",
+        "counter",
+        "Bytes written by the unit.",
+        |u| u.io_write_bytes,
+        1.0,
+    ),
+    (
+        "systemd_unit_ip_ingress_bytes_total",
+        "counter",
+        "IP bytes received by the unit.",
+        |u| u.ip_ingress_bytes,
+        1.0,
+    ),
+    (
+        "systemd_unit_ip_egress_bytes_total",
+        "counter",
+        "IP bytes sent by the unit.",
+        |u| u.ip_egress_bytes,
+        1.0,
+    ),
+];
+
+/// Matches a unit name against a glob like `systemctl list-units` patterns: `*`, `?` and `[...]` classes
+/// (`[a-z]`, `[!0-9]`).
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    glob_match_at(&pattern, &name)
+}
+
+fn glob_match_at(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| glob_match_at(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && glob_match_at(&pattern[1..], &name[1..]),
+        Some('[') => match (name.first(), class_end(pattern)) {
+            (Some(c), Some(end)) => {
+                class_matches(&pattern[1..end], *c) && glob_match_at(&pattern[end + 1..], &name[1..])
+            }
+            // An unterminated class is a literal `[`.
+            (Some('['), None) => glob_match_at(&pattern[1..], &name[1..]),
+            _ => false,
+        },
+        Some(p) => name.first() == Some(p) && glob_match_at(&pattern[1..], &name[1..]),
+    }
+}
+
+// Index of the `]` closing the class at the start of `pattern`; a `]` right after `[` or `[!` is literal.
+fn class_end(pattern: &[char]) -> Option<usize> {
+    let start = if pattern.get(1) == Some(&'!') { 2 } else { 1 };
+    (start + 1..pattern.len()).find(|i| pattern[*i] == ']')
+}
+
+fn class_matches(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut matched = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            matched |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    matched != negated
+}
+
+/// Which units to export: those matching any `include` glob (every unit when there are none) and no
+/// `exclude` glob.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl UnitFilter {
+    pub fn matches(&self, name: &str) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|glob| glob_match(glob, name));
+        included && !self.exclude.iter().any(|glob| glob_match(glob, name))
+    }
+}
+
+/// What is exported for one unit.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitMetrics {
+    pub name: String,
+    pub active_state: String,
+    pub sub_state: String,
+    /// Services only.
+    pub n_restarts: Option<u64>,
+    /// Realtime of the last start, in microseconds since the epoch.
+    pub active_enter_timestamp: Option<u64>,
+    /// Accounting counters; `None` for units without a cgroup or not read yet.
+    pub usage: Option<UsageSample>,
+}
+
+impl UnitMetrics {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            active_state: String::new(),
+            sub_state: String::new(),
+            n_restarts: None,
+            active_enter_timestamp: None,
+            usage: None,
+        }
+    }
+
+    pub fn unit_type(&self) -> &str {
+        self.name.rsplit('.').next().unwrap_or_default()
+    }
+
+    /// Updates from properties of the Unit interface or the unit's type-specific interface, whichever
+    /// `properties` came from.
+    pub fn update(&mut self, properties: &arg::PropMap) {
+        if let Some(state) = property_str(properties, "ActiveState") {
+            self.active_state = state.to_string();
+        }
+        if let Some(state) = property_str(properties, "SubState") {
+            self.sub_state = state.to_string();
+        }
+        if properties.contains_key("ActiveEnterTimestamp") {
+            self.active_enter_timestamp = property_timestamp(properties, "ActiveEnterTimestamp");
+        }
+        if let Some(restarts) = property_i64(properties, "NRestarts") {
+            self.n_restarts = Some(restarts as u64);
+        }
+        if properties.contains_key("CPUUsageNSec") || properties.contains_key("MemoryCurrent") {
+            self.usage = Some(UsageSample::from_properties(&self.name, properties));
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        ["active", "reloading", "activating", "deactivating"].contains(&self.active_state.as_str())
+    }
+}
+
+/// Everything the exporter serves; `render` writes it in the Prometheus text format.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetricsState {
+    pub units: BTreeMap<String, UnitMetrics>,
+    pub n_failed_units: Option<u64>,
+    pub n_jobs: Option<u64>,
+    /// Finished jobs by result (done, failed, canceled, ...) since the exporter started.
+    pub job_results: BTreeMap<String, u64>,
+    /// Realtime of each boot stage that has one, in microseconds since the epoch.
+    pub boot_timestamps: Vec<(&'static str, u64)>,
+}
+
+impl MetricsState {
+    pub fn update_manager(&mut self, properties: &arg::PropMap) {
+        if properties.contains_key("NFailedUnits") {
+            self.n_failed_units = property_i64(properties, "NFailedUnits").map(|n| n as u64);
+        }
+        if properties.contains_key("NJobs") {
+            self.n_jobs = property_i64(properties, "NJobs").map(|n| n as u64);
+        }
+        let stages: Vec<(&'static str, u64)> = BOOT_STAGES
+            .iter()
+            .filter_map(|(stage, property)| property_timestamp(properties, property).map(|usec| (*stage, usec)))
+            .collect();
+        if !stages.is_empty() {
+            self.boot_timestamps = stages;
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let units: Vec<&UnitMetrics> = self.units.values().collect();
+
+        family(
+            &mut out,
+            "systemd_unit_state",
+            "gauge",
+            "Active state of the unit, one series per state.",
+        );
+        for unit in units.iter() {
+            for state in ACTIVE_STATES.iter() {
+                let value = if unit.active_state == *state { 1 } else { 0 };
+                sample(
+                    &mut out,
+                    "systemd_unit_state",
+                    &unit_labels(unit, &[("state", state)]),
+                    value,
+                );
+            }
+        }
+        family(
+            &mut out,
+            "systemd_unit_sub_state",
+            "gauge",
+            "Current sub-state of the unit, always 1.",
+        );
+        for unit in units.iter().filter(|unit| !unit.sub_state.is_empty()) {
+            sample(
+                &mut out,
+                "systemd_unit_sub_state",
+                &unit_labels(unit, &[("state", &unit.sub_state)]),
+                1,
+            );
+        }
+        family(
+            &mut out,
+            "systemd_service_restart_total",
+            "counter",
+            "Automatic restarts of the service (NRestarts).",
+        );
+        for unit in units.iter() {
+            if let Some(restarts) = unit.n_restarts {
+                sample(
+                    &mut out,
+                    "systemd_service_restart_total",
+                    &unit_labels(unit, &[]),
+                    restarts,
+                );
+            }
+        }
+        family(
+            &mut out,
+            "systemd_unit_start_time_seconds",
+            "gauge",
+            "When the unit last became active.",
+        );
+        for unit in units.iter() {
+            if let Some(usec) = unit.active_enter_timestamp {
+                sample(
+                    &mut out,
+                    "systemd_unit_start_time_seconds",
+                    &unit_labels(unit, &[]),
+                    seconds(usec),
+                );
+            }
+        }
+
+        let usage: Vec<(&UnitMetrics, &UsageSample)> = units
+            .iter()
+            .filter_map(|unit| unit.usage.as_ref().map(|usage| (*unit, usage)))
+            .collect();
+        for (name, kind, help, read, scale) in USAGE_FAMILIES.iter() {
+            family(&mut out, name, kind, help);
+            for (unit, usage) in usage.iter() {
+                if let Some(value) = read(usage) {
+                    sample(&mut out, name, &unit_labels(unit, &[]), value as f64 / scale);
+                }
+            }
+        }
+
+        if let Some(failed) = self.n_failed_units {
+            family(&mut out, "systemd_nfailed_units", "gauge", "Units in the failed state.");
+            sample(&mut out, "systemd_nfailed_units", "", failed);
+        }
+        if let Some(jobs) = self.n_jobs {
+            family(&mut out, "systemd_jobs", "gauge", "Jobs queued in the manager.");
+            sample(&mut out, "systemd_jobs", "", jobs);
+        }
+        family(
+            &mut out,
+            "systemd_job_results_total",
+            "counter",
+            "Finished jobs by result.",
+        );
+        for (result, count) in self.job_results.iter() {
+            sample(
+                &mut out,
+                "systemd_job_results_total",
+                &labels(&[("result", result)]),
+                *count,
+            );
+        }
+        family(
+            &mut out,
+            "systemd_boot_timestamp_seconds",
+            "gauge",
+            "When each boot stage started.",
+        );
+        for (stage, usec) in self.boot_timestamps.iter() {
+            sample(
+                &mut out,
+                "systemd_boot_timestamp_seconds",
+                &labels(&[("stage", stage)]),
+                seconds(*usec),
+            );
+        }
+        out
+    }
+}
+
+fn seconds(usec: u64) -> f64 {
+    usec as f64 / 1e6
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample<V: std::fmt::Display>(out: &mut String, name: &str, labels: &str, value: V) {
+    let _ = writeln!(out, "{}{} {}", name, labels, value);
+}
+
+fn unit_labels(unit: &UnitMetrics, extra: &[(&str, &str)]) -> String {
+    let mut all = vec![("name", unit.name.as_str()), ("type", unit.unit_type())];
+    all.extend_from_slice(extra);
+    labels(&all)
+}
+
+fn labels(labels: &[(&str, &str)]) -> String {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+/// Keeps a `MetricsState` current for the units selected by a `UnitFilter`.
+///
+/// States, restarts and start times follow the manager's signals once `watch` runs, so a scrape doesn't list
+/// every unit. Accounting counters have no change signals; `scrape` reads them for running units only.
+#[derive(Clone, Debug)]
+pub struct Exporter {
+    manager: SystemdManager,
+    filter: UnitFilter,
+    state: Arc<Mutex<MetricsState>>,
+}
+
+impl Exporter {
+    pub fn new(manager: SystemdManager, filter: UnitFilter) -> Self {
+        Self {
+            manager,
+            filter,
+            state: Arc::new(Mutex::new(MetricsState::default())),
+        }
+    }
+
+    pub fn state(&self) -> MetricsState {
+        self.state.lock().unwrap().clone()
+    }
+
+    /// Reads the manager and every matching unit from scratch.
+    pub async fn refresh(&self) -> Result<(), dbus::MethodErr> {
+        self.refresh_manager().await?;
+        let units: Vec<SystemdUnitStatus> = self
+            .manager
+            .list_units()
+            .await?
+            .into_iter()
+            .map(SystemdUnitStatus::from)
+            .filter(|unit| self.filter.matches(&unit.name))
+            .collect();
+        self.state
+            .lock()
+            .unwrap()
+            .units
+            .retain(|name, _| units.iter().any(|unit| &unit.name == name));
+        for unit in units.iter() {
+            if let Err(e) = self.refresh_unit(&unit.name).await {
+                debug!("{}: {:?}", unit.name, e);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn refresh_manager(&self) -> Result<(), dbus::MethodErr> {
+        let properties = self.manager.get_manager_properties().await?;
+        self.state.lock().unwrap().update_manager(&properties);
+        Ok(())
+    }
+
+    /// Reads the state of `name` and, if it has a cgroup, its accounting and restarts.
+    pub async fn refresh_unit(&self, name: &str) -> Result<(), dbus::MethodErr> {
+        let mut unit = UnitMetrics::new(name);
+        unit.update(&self.manager.get_unit_properties(name, SYSTEMD_UNIT.interface).await?);
+        if let Some(interface) = cgroup_interface(name) {
+            unit.update(&self.manager.get_unit_properties(name, interface).await?);
+        }
+        self.state.lock().unwrap().units.insert(name.to_string(), unit);
+        Ok(())
+    }
+
+    /// Refreshes accounting of running units and renders the metrics.
+    pub async fn scrape(&self) -> String {
+        let running: Vec<String> = {
+            let state = self.state.lock().unwrap();
+            state
+                .units
+                .values()
+                .filter(|unit| unit.is_running() && cgroup_interface(&unit.name).is_some())
+                .map(|unit| unit.name.clone())
+                .collect()
+        };
+        for name in running.iter() {
+            let interface = cgroup_interface(name).unwrap_or_default();
+            match self.manager.get_unit_properties(name, interface).await {
+                Ok(properties) => {
+                    if let Some(unit) = self.state.lock().unwrap().units.get_mut(name) {
+                        unit.update(&properties);
+                    }
+                }
+                Err(e) => debug!("{}: {:?}", name, e),
+            }
+        }
+        self.state.lock().unwrap().render()
+    }
+
+    /// Subscribes to the manager's signals, does a full `refresh`, then applies changes as they're signalled
+    /// until the connection closes.
+    pub async fn watch(&self) -> Result<(), dbus::MethodErr> {
+        let connection = self.manager.connection().await?;
+        let sender = dbus::strings::BusName::from(SYSTEMD.service);
+        let (changes_match, mut changes) = connection
+            .add_match(DBusPropertiesPropertiesChanged::match_rule(Some(&sender), None).static_clone())
+            .await
+            .map_err(dbus::MethodErr::from)?
+            .stream::<DBusPropertiesPropertiesChanged>();
+        let (new_units_match, mut new_units) = connection
+            .add_match(Systemd1ManagerUnitNew::match_rule(Some(&sender), None).static_clone())
+            .await
+            .map_err(dbus::MethodErr::from)?
+            .stream::<Systemd1ManagerUnitNew>();
+        let (removed_units_match, mut removed_units) = connection
+            .add_match(Systemd1ManagerUnitRemoved::match_rule(Some(&sender), None).static_clone())
+            .await
+            .map_err(dbus::MethodErr::from)?
+            .stream::<Systemd1ManagerUnitRemoved>();
+        let (new_jobs_match, mut new_jobs) = connection
+            .add_match(Systemd1ManagerJobNew::match_rule(Some(&sender), None).static_clone())
+            .await
+            .map_err(dbus::MethodErr::from)?
+            .stream::<Systemd1ManagerJobNew>();
+        let (removed_jobs_match, mut removed_jobs) = connection
+            .add_match(Systemd1ManagerJobRemoved::match_rule(Some(&sender), None).static_clone())
+            .await
+            .map_err(dbus::MethodErr::from)?
+            .stream::<Systemd1ManagerJobRemoved>();
+
+        let watched = async {
+            self.manager.subscribe().await?;
+            self.refresh().await?;
+            loop {
+                tokio::select! {
+                    Some((message, changed)) = changes.next() => {
+                        let name = message.path().and_then(|path| unit_name_from_object_path(&path));
+                        match name {
+                            Some(name) if self.filter.matches(&name) => {
+                                let mut state = self.state.lock().unwrap();
+                                state.units.entry(name.clone()).or_insert_with(|| UnitMetrics::new(&name))
+                                    .update(&changed.changed_properties);
+                            }
+                            Some(_) => {}
+                            None => self.state.lock().unwrap().update_manager(&changed.changed_properties),
+                        }
+                    }
+                    Some((_, unit)) = new_units.next() => {
+                        if self.filter.matches(&unit.arg0) {
+                            if let Err(e) = self.refresh_unit(&unit.arg0).await {
+                                debug!("{}: {:?}", unit.arg0, e);
+                            }
+                        }
+                    }
+                    Some((_, unit)) = removed_units.next() => {
+                        self.state.lock().unwrap().units.remove(&unit.arg0);
+                    }
+                    Some(_) = new_jobs.next() => {
+                        if let Err(e) = self.refresh_manager().await {
+                            debug!("{:?}", e);
+                        }
+                    }
+                    Some((_, job)) = removed_jobs.next() => {
+                        *self.state.lock().unwrap().job_results.entry(job.result.clone()).or_insert(0) += 1;
+                        if let Err(e) = self.refresh_manager().await {
+                            debug!("{:?}", e);
+                        }
+                    }
+                    else => break,
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        for token in [
+            changes_match.token(),
+            new_units_match.token(),
+            removed_units_match.token(),
+            new_jobs_match.token(),
+            removed_jobs_match.token(),
+        ]
+        .iter()
+        {
+            if let Err(e) = connection.remove_match(*token).await {
+                debug!("{:?}", e);
+            }
+        }
+        watched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::{PropMap, RefArg, Variant};
+
+    use super::{glob_match, MetricsState, UnitFilter, UnitMetrics};
+
+    fn properties(values: Vec<(&str, Box<dyn RefArg>)>) -> PropMap {
+        values
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), Variant(value)))
+            .collect()
+    }
+
+    #[test]
+    fn matches_unit_globs() {
+        assert!(glob_match("*.service", "sshd.service"));
+        assert!(glob_match("getty@tty?.service", "getty@tty1.service"));
+        assert!(glob_match("sys-[a-c]*.mount", "sys-bus.mount"));
+        assert!(!glob_match("sys-[!a-c]*.mount", "sys-bus.mount"));
+        assert!(!glob_match("*.service", "sshd.socket"));
+
+        let filter = UnitFilter {
+            include: vec!["*.service".to_string()],
+            exclude: vec!["systemd-*".to_string()],
+        };
+        assert!(filter.matches("nginx.service"));
+        assert!(!filter.matches("systemd-journald.service"));
+        assert!(!filter.matches("nginx.socket"));
+        assert!(UnitFilter::default().matches("anything.timer"));
+    }
+
+    #[test]
+    fn renders_prometheus_text() {
+        let mut unit = UnitMetrics::new("web\"1\".service");
+        unit.update(&properties(vec![
+            ("ActiveState", Box::new("active".to_string())),
+            ("SubState", Box::new("running".to_string())),
+            ("ActiveEnterTimestamp", Box::new(1_600_000_000_500_000u64)),
+            ("NRestarts", Box::new(2u32)),
+            ("CPUUsageNSec", Box::new(1_500_000_000u64)),
+            ("MemoryCurrent", Box::new(4096u64)),
+            ("IPIngressBytes", Box::new(u64::MAX)),
+        ]));
+        let mut state = MetricsState::default();
+        state.units.insert(unit.name.clone(), unit);
+        state.update_manager(&properties(vec![
+            ("NFailedUnits", Box::new(1u32)),
+            ("NJobs", Box::new(0u32)),
+            ("KernelTimestamp", Box::new(1_599_999_990_000_000u64)),
+            ("FirmwareTimestamp", Box::new(0u64)),
+        ]));
+        state.job_results.insert("done".to_string(), 3);
+
+        let text = state.render();
+        let labels = r#"name="web\"1\".service",type="service""#;
+        for line in [
+            format!("systemd_unit_state{{{},state=\"active\"}} 1", labels),
+            format!("systemd_unit_state{{{},state=\"failed\"}} 0", labels),
+            format!("systemd_unit_state{{{},state=\"maintenance\"}} 0", labels),
+            format!("systemd_unit_state{{{},state=\"refreshing\"}} 0", labels),
+            format!("systemd_unit_sub_state{{{},state=\"running\"}} 1", labels),
+            format!("systemd_service_restart_total{{{}}} 2", labels),
+            format!("systemd_unit_start_time_seconds{{{}}} 1600000000.5", labels),
+            format!("systemd_unit_cpu_seconds_total{{{}}} 1.5", labels),
+            format!("systemd_unit_memory_bytes{{{}}} 4096", labels),
+            "systemd_nfailed_units 1".to_string(),
+            "systemd_jobs 0".to_string(),
+            "systemd_job_results_total{result=\"done\"} 3".to_string(),
+            "systemd_boot_timestamp_seconds{stage=\"kernel\"} 1599999990".to_string(),
+            "# TYPE systemd_unit_cpu_seconds_total counter".to_string(),
+        ]
+        .iter()
+        {
+            assert!(text.lines().any(|l| l == line), "missing {:?} in\n{}", line, text);
+        }
+        assert!(!text.contains("systemd_unit_ip_ingress_bytes_total{"));
+        assert!(!text.contains("stage=\"firmware\""));
+    }
+}
@@ -1,15 +1,153 @@
-use strum::{AsRefStr, AsStaticStr, IntoStaticStr};
+use dbus::arg;
+use strum::{AsRefStr, AsStaticStr, EnumString, IntoStaticStr};
 
-#[derive(AsRefStr, AsStaticStr, IntoStaticStr)]
+/// The job mode passed along with unit operations; serialized in the kebab-case form systemd expects.
+#[derive(AsRefStr, AsStaticStr, IntoStaticStr, EnumString, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+#[strum(serialize_all = "kebab-case")]
 pub enum Mode {
     /// Start the unit and its dependencies, while maybe replacing existing jobs related to unit.
     Replace,
+    /// Like replace, but mark the new jobs as irreversible so later conflicting jobs can't replace them.
+    ReplaceIrreversibly,
     /// Start the unit and its dependencies but fail if this would affect an existing job.
     Fail,
-    /// Start the unit and terminate all units that aren't dependencies of it.
+    /// Start the unit and terminate all units that aren't dependencies of it. Only valid for targets.
     Isolate,
+    /// Start the unit after cancelling all queued jobs.
+    Flush,
     /// Start a unit but ignore all its dependencies.
     IgnoreDependencies,
     /// Start a unit but only ignore the requirement dependencies.
     IgnoreRequirements,
+    /// Stop the unit along with units that trigger it (sockets, paths, timers). Only valid for stop.
+    Triggering,
+    /// Propagate restart jobs to the units depending on this one. Only valid for start.
+    RestartDependencies,
+}
+
+/// The kind of job a unit operation queues, which decides the modes it takes. Try-restart and reload-or-restart
+/// queue restart jobs.
+#[derive(AsRefStr, Clone, Copy, Debug, PartialEq, Eq)]
+#[strum(serialize_all = "kebab-case")]
+pub enum JobType {
+    Start,
+    Stop,
+    Reload,
+    Restart,
+}
+
+impl Mode {
+    /// Rejects mode, job and unit combinations systemd would refuse, before anything is sent over the bus.
+    pub fn validate(&self, job: JobType, name: &str) -> Result<(), dbus::MethodErr> {
+        let invalid = |message: String| Err(dbus::MethodErr::invalid_arg(&message));
+        match self {
+            Mode::Isolate if !name.ends_with(".target") => {
+                invalid(format!("mode isolate is only valid for target units, got {}", name))
+            }
+            Mode::Isolate | Mode::RestartDependencies if job != JobType::Start => invalid(format!(
+                "mode {} is only valid for start, got {}",
+                self.as_ref(),
+                job.as_ref()
+            )),
+            Mode::Triggering if job != JobType::Stop => {
+                invalid(format!("mode triggering is only valid for stop, got {}", job.as_ref()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl arg::Arg for Mode {
+    const ARG_TYPE: arg::ArgType = arg::ArgType::String;
+
+    fn signature() -> dbus::Signature<'static> {
+        <&str as arg::Arg>::signature()
+    }
+}
+
+impl arg::Append for Mode {
+    fn append_by_ref(&self, i: &mut arg::IterAppend<'_>) {
+        i.append(self.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::{JobType, Mode};
+    use crate::{Systemd1Manager, SystemdManager};
+
+    // The mode as appended to a method call and read back off the message.
+    fn sent_mode(mode: &Mode) -> String {
+        let message = dbus::Message::new_method_call(
+            "org.freedesktop.systemd1",
+            "/org/freedesktop/systemd1",
+            "org.freedesktop.systemd1.Manager",
+            "StartUnit",
+        )
+        .unwrap()
+        .append2("sshd.service", mode);
+        let (_, sent): (String, String) = message.read2().unwrap();
+        sent
+    }
+
+    #[test]
+    fn modes_are_sent_as_systemd_job_mode_strings() {
+        let expected = [
+            (Mode::Replace, "replace"),
+            (Mode::ReplaceIrreversibly, "replace-irreversibly"),
+            (Mode::Fail, "fail"),
+            (Mode::Isolate, "isolate"),
+            (Mode::Flush, "flush"),
+            (Mode::IgnoreDependencies, "ignore-dependencies"),
+            (Mode::IgnoreRequirements, "ignore-requirements"),
+            (Mode::Triggering, "triggering"),
+            (Mode::RestartDependencies, "restart-dependencies"),
+        ];
+
+        for (mode, wire) in expected.iter() {
+            assert_eq!(sent_mode(mode), *wire);
+            assert_eq!(Mode::from_str(wire).unwrap(), *mode);
+        }
+    }
+
+    #[test]
+    fn modes_are_only_valid_for_their_operations() {
+        assert!(Mode::Isolate.validate(JobType::Start, "multi-user.target").is_ok());
+        assert!(Mode::Isolate.validate(JobType::Start, "sshd.service").is_err());
+        assert!(Mode::Isolate.validate(JobType::Stop, "multi-user.target").is_err());
+        assert!(Mode::Triggering.validate(JobType::Stop, "sshd.socket").is_ok());
+        assert!(Mode::Triggering.validate(JobType::Start, "sshd.socket").is_err());
+        assert!(Mode::Triggering.validate(JobType::Restart, "sshd.socket").is_err());
+        assert!(Mode::RestartDependencies
+            .validate(JobType::Start, "sshd.service")
+            .is_ok());
+        assert!(Mode::RestartDependencies
+            .validate(JobType::Stop, "sshd.service")
+            .is_err());
+        assert!(Mode::Replace.validate(JobType::Reload, "sshd.service").is_ok());
+    }
+
+    // Rejected with InvalidArgs before a connection is made, so this needs no bus.
+    #[tokio::test]
+    async fn manager_rejects_invalid_modes() {
+        let systemd = SystemdManager::default();
+        let invalid_args = "org.freedesktop.DBus.Error.InvalidArgs";
+
+        let isolate = systemd.start_unit("sshd.service", &Mode::Isolate).await.unwrap_err();
+        assert_eq!(&**isolate.errorname(), invalid_args);
+        let triggering = systemd.start_unit("sshd.socket", &Mode::Triggering).await.unwrap_err();
+        assert_eq!(&**triggering.errorname(), invalid_args);
+        let restart = systemd
+            .stop_unit("sshd.service", &Mode::RestartDependencies)
+            .await
+            .unwrap_err();
+        assert_eq!(&**restart.errorname(), invalid_args);
+    }
 }
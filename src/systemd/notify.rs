@@ -0,0 +1,351 @@
+use std::{
+    fmt, io, mem,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+            net::{SocketAddr, UnixDatagram},
+        },
+    },
+    path::Path,
+    ptr,
+    time::Duration,
+};
+
+const NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+// How long NotifyListener::recv waits, so a test expecting a message that never comes fails instead of hanging.
+const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_MESSAGE: usize = 4096;
+// SCM_MAX_FD, the most descriptors the kernel passes in one message.
+const MAX_FDS: usize = 253;
+
+/// One assignment of the sd_notify(3) protocol.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NotifyState {
+    /// Startup is finished; for Type=notify services this completes the start job.
+    Ready,
+    /// The service is reloading its configuration; send Ready when done.
+    Reloading,
+    /// The service is shutting down.
+    Stopping,
+    /// Free-form status shown by `systemctl status`; a single line.
+    Status(String),
+    /// The errno-style error the service failed with.
+    Errno(i32),
+    /// The main process, if it isn't the one sending.
+    MainPid(u32),
+    /// Asks for more time for the current start, reload or stop, counted from now.
+    ExtendTimeout(Duration),
+    /// Keep-alive ping for WatchdogSec=; see [`spawn_watchdog`](super::spawn_watchdog).
+    Watchdog,
+    /// Reports a watchdog failure right away instead of waiting for the timeout.
+    WatchdogTrigger,
+    /// Asks the service manager to keep the descriptors sent along; see [`store_fds`](super::store_fds).
+    FdStore,
+    /// Asks the service manager to close the stored descriptors named by FdName.
+    FdStoreRemove,
+    /// The name of the descriptors stored or removed.
+    FdName(String),
+}
+
+impl fmt::Display for NotifyState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifyState::Ready => f.write_str("READY=1"),
+            NotifyState::Reloading => f.write_str("RELOADING=1"),
+            NotifyState::Stopping => f.write_str("STOPPING=1"),
+            NotifyState::Status(status) => write!(f, "STATUS={}", status),
+            NotifyState::Errno(errno) => write!(f, "ERRNO={}", errno),
+            NotifyState::MainPid(pid) => write!(f, "MAINPID={}", pid),
+            NotifyState::ExtendTimeout(timeout) => write!(f, "EXTEND_TIMEOUT_USEC={}", timeout.as_micros()),
+            NotifyState::Watchdog => f.write_str("WATCHDOG=1"),
+            NotifyState::WatchdogTrigger => f.write_str("WATCHDOG=trigger"),
+            NotifyState::FdStore => f.write_str("FDSTORE=1"),
+            NotifyState::FdStoreRemove => f.write_str("FDSTOREREMOVE=1"),
+            NotifyState::FdName(name) => write!(f, "FDNAME={}", name),
+        }
+    }
+}
+
+/// Sends notifications to the service manager's socket.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notifier {
+    socket: String,
+}
+
+impl Notifier {
+    /// A notifier for `$NOTIFY_SOCKET`, or `None` when the process wasn't started with one (it isn't a
+    /// Type=notify service, or runs outside systemd).
+    pub fn from_env() -> Option<Self> {
+        std::env::var(NOTIFY_SOCKET)
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(Self::new)
+    }
+
+    /// A notifier for a socket path, or an abstract socket name starting with `@`.
+    pub fn new<S: Into<String>>(socket: S) -> Self {
+        Self { socket: socket.into() }
+    }
+
+    pub fn socket(&self) -> &str {
+        &self.socket
+    }
+
+    /// Sends `states` as one message.
+    pub fn notify(&self, states: &[NotifyState]) -> io::Result<()> {
+        let message = message(states)?;
+        let address = socket_address(&self.socket)?;
+        let socket = UnixDatagram::unbound()?;
+        let sent = socket.send_to_addr(message.as_bytes(), &address)?;
+        if sent != message.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "notification truncated"));
+        }
+        Ok(())
+    }
+
+    /// Sends `states` as one message with `fds` attached (SCM_RIGHTS), like sd_pid_notify_with_fds(3).
+    pub fn notify_with_fds(&self, states: &[NotifyState], fds: &[BorrowedFd<'_>]) -> io::Result<()> {
+        if fds.is_empty() {
+            return self.notify(states);
+        }
+        if fds.len() > MAX_FDS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("at most {} descriptors fit in one message", MAX_FDS),
+            ));
+        }
+        let message = message(states)?;
+        let socket = UnixDatagram::unbound()?;
+        socket.connect_addr(&socket_address(&self.socket)?)?;
+
+        let raw: Vec<RawFd> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+        let data_len = mem::size_of_val(raw.as_slice()) as u32;
+        let mut iov = libc::iovec {
+            iov_base: message.as_ptr() as *mut libc::c_void,
+            iov_len: message.len(),
+        };
+        // SAFETY: CMSG_SPACE only computes a size.
+        let control_len = unsafe { libc::CMSG_SPACE(data_len) } as usize;
+        // u64s keep the buffer aligned for cmsghdr.
+        let mut control = vec![0u64; control_len.div_ceil(8)];
+        // SAFETY: msghdr is plain data; the pointers set below outlive the sendmsg call, and the control buffer
+        // has room for one cmsghdr carrying `raw`.
+        let sent = unsafe {
+            let mut header: libc::msghdr = mem::zeroed();
+            header.msg_iov = &mut iov;
+            header.msg_iovlen = 1;
+            header.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            header.msg_controllen = control_len as _;
+            let cmsg = libc::CMSG_FIRSTHDR(&header);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(data_len) as _;
+            ptr::copy_nonoverlapping(raw.as_ptr() as *const u8, libc::CMSG_DATA(cmsg), data_len as usize);
+            libc::sendmsg(socket.as_raw_fd(), &header, libc::MSG_NOSIGNAL)
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if sent as usize != message.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "notification truncated"));
+        }
+        Ok(())
+    }
+}
+
+/// Sends `states` to `$NOTIFY_SOCKET`, like sd_notify(3). Returns `false` without sending anything when the
+/// variable isn't set.
+pub fn notify(states: &[NotifyState]) -> io::Result<bool> {
+    match Notifier::from_env() {
+        Some(notifier) => notifier.notify(states).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+fn message(states: &[NotifyState]) -> io::Result<String> {
+    let mut lines = vec![];
+    for state in states.iter() {
+        if let NotifyState::Status(status) = state {
+            if status.contains('\n') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "STATUS must be a single line",
+                ));
+            }
+        }
+        lines.push(state.to_string());
+    }
+    if lines.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no states to send"));
+    }
+    Ok(lines.join("\n"))
+}
+
+fn socket_address(socket: &str) -> io::Result<SocketAddr> {
+    if let Some(name) = socket.strip_prefix('@') {
+        SocketAddr::from_abstract_name(name)
+    } else if socket.starts_with('/') {
+        SocketAddr::from_pathname(socket)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported {} {}", NOTIFY_SOCKET, socket),
+        ))
+    }
+}
+
+/// A datagram socket standing in for the service manager, for testing services' notifications.
+///
+/// Point the code under test at [`NotifyListener::socket`] (as `$NOTIFY_SOCKET` or through [`Notifier::new`])
+/// and read back what it sent with [`NotifyListener::recv`].
+pub struct NotifyListener {
+    socket: UnixDatagram,
+    address: String,
+}
+
+impl NotifyListener {
+    /// Binds a socket at `path`.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let socket = UnixDatagram::bind(path)?;
+        Self::listen(socket, path.to_string_lossy().into_owned())
+    }
+
+    /// Binds a socket in the abstract namespace, which needs no cleanup.
+    pub fn bind_abstract(name: &str) -> io::Result<Self> {
+        let socket = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(name)?)?;
+        Self::listen(socket, format!("@{}", name))
+    }
+
+    fn listen(socket: UnixDatagram, address: String) -> io::Result<Self> {
+        socket.set_read_timeout(Some(RECV_TIMEOUT))?;
+        Ok(Self { socket, address })
+    }
+
+    /// The value to use for `$NOTIFY_SOCKET`.
+    pub fn socket(&self) -> &str {
+        &self.address
+    }
+
+    /// The assignments of the next message, waiting up to 5 seconds for it. Descriptors sent along are closed.
+    pub fn recv(&self) -> io::Result<Vec<String>> {
+        self.recv_with_fds().map(|(assignments, _)| assignments)
+    }
+
+    /// The assignments of the next message and the descriptors sent along, waiting up to 5 seconds for it.
+    pub fn recv_with_fds(&self) -> io::Result<(Vec<String>, Vec<OwnedFd>)> {
+        let mut buffer = vec![0u8; MAX_MESSAGE];
+        let mut iov = libc::iovec {
+            iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buffer.len(),
+        };
+        // SAFETY: CMSG_SPACE only computes a size.
+        let control_len = unsafe { libc::CMSG_SPACE((MAX_FDS * mem::size_of::<RawFd>()) as u32) } as usize;
+        let mut control = vec![0u64; control_len.div_ceil(8)];
+        let mut fds = vec![];
+        // SAFETY: as in notify_with_fds; the kernel fills in at most msg_controllen bytes of cmsghdrs, and each
+        // descriptor received is owned by this process.
+        let read = unsafe {
+            let mut header: libc::msghdr = mem::zeroed();
+            header.msg_iov = &mut iov;
+            header.msg_iovlen = 1;
+            header.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            header.msg_controllen = control_len as _;
+            let read = libc::recvmsg(self.socket.as_raw_fd(), &mut header, libc::MSG_CMSG_CLOEXEC);
+            if read < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut cmsg = libc::CMSG_FIRSTHDR(&header);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                    let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / mem::size_of::<RawFd>();
+                    for i in 0..count {
+                        fds.push(OwnedFd::from_raw_fd(ptr::read_unaligned(data.add(i))));
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&header, cmsg);
+            }
+            read as usize
+        };
+        let assignments = String::from_utf8_lossy(&buffer[..read])
+            .lines()
+            .map(String::from)
+            .collect();
+        Ok((assignments, fds))
+    }
+}
+
+impl Drop for NotifyListener {
+    fn drop(&mut self) {
+        if self.address.starts_with('/') {
+            let _ = std::fs::remove_file(&self.address);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Notifier, NotifyListener, NotifyState};
+
+    fn listener_name(test: &str) -> String {
+        format!("dbus-systemd-{}-{}", test, std::process::id())
+    }
+
+    #[test]
+    fn sends_states_as_one_message() {
+        let listener = NotifyListener::bind_abstract(&listener_name("notify")).unwrap();
+        let notifier = Notifier::new(listener.socket());
+        notifier
+            .notify(&[
+                NotifyState::Ready,
+                NotifyState::Status("Serving 3 clients".to_string()),
+                NotifyState::MainPid(4242),
+            ])
+            .unwrap();
+        assert_eq!(
+            listener.recv().unwrap(),
+            vec!["READY=1", "STATUS=Serving 3 clients", "MAINPID=4242"]
+        );
+
+        notifier
+            .notify(&[
+                NotifyState::Reloading,
+                NotifyState::ExtendTimeout(Duration::from_secs(30)),
+            ])
+            .unwrap();
+        assert_eq!(
+            listener.recv().unwrap(),
+            vec!["RELOADING=1", "EXTEND_TIMEOUT_USEC=30000000"]
+        );
+
+        notifier
+            .notify(&[NotifyState::Stopping, NotifyState::Errno(5)])
+            .unwrap();
+        assert_eq!(listener.recv().unwrap(), vec!["STOPPING=1", "ERRNO=5"]);
+    }
+
+    #[test]
+    fn sends_to_socket_paths() {
+        let path = std::env::temp_dir().join(listener_name("notify-path"));
+        let listener = NotifyListener::bind(&path).unwrap();
+        Notifier::new(listener.socket()).notify(&[NotifyState::Ready]).unwrap();
+        assert_eq!(listener.recv().unwrap(), vec!["READY=1"]);
+        drop(listener);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn rejects_invalid_notifications() {
+        let notifier = Notifier::new("@unused");
+        assert!(notifier.notify(&[]).is_err());
+        assert!(notifier
+            .notify(&[NotifyState::Status("two\nlines".to_string())])
+            .is_err());
+        assert!(Notifier::new("relative/socket").notify(&[NotifyState::Ready]).is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+
+use crate::dbus::DbusConnectionManager;
+
+use super::{property_str, unit_object_path, Systemd1Manager, SystemdManager, SYSTEMD};
+
+pub type UnitProcessDto = (String, u32, String);
+
+// Unit types that have a cgroup, with the interface carrying their cgroup properties and GetProcesses.
+const CGROUP_UNIT_TYPES: &[(&str, &str)] = &[
+    ("service", "org.freedesktop.systemd1.Service"),
+    ("socket", "org.freedesktop.systemd1.Socket"),
+    ("mount", "org.freedesktop.systemd1.Mount"),
+    ("swap", "org.freedesktop.systemd1.Swap"),
+    ("scope", "org.freedesktop.systemd1.Scope"),
+    ("slice", "org.freedesktop.systemd1.Slice"),
+];
+
+/// The type-specific interface of a unit that has a cgroup, e.g. org.freedesktop.systemd1.Service.
+pub(crate) fn cgroup_interface(name: &str) -> Option<&'static str> {
+    let suffix = name.rsplit('.').next().unwrap_or_default();
+    CGROUP_UNIT_TYPES
+        .iter()
+        .find(|(unit_type, _)| *unit_type == suffix)
+        .map(|(_, interface)| *interface)
+}
+
+/// A process in a unit's cgroup, as returned by GetUnitProcesses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitProcess {
+    /// The cgroup the process is in, relative to the cgroup root; a sub-cgroup of the unit's for delegated units.
+    pub cgroup_path: String,
+    pub pid: u32,
+    pub cmdline: String,
+}
+
+impl From<UnitProcessDto> for UnitProcess {
+    fn from(p: UnitProcessDto) -> Self {
+        Self {
+            cgroup_path: p.0,
+            pid: p.1,
+            cmdline: p.2,
+        }
+    }
+}
+
+/// Processes of a cgroup and its sub-cgroups; `Display` draws it like `systemd-cgls` and `systemctl status`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CGroupTree {
+    pub path: String,
+    pub processes: Vec<UnitProcess>,
+    pub children: Vec<CGroupTree>,
+}
+
+impl CGroupTree {
+    /// Groups `processes` by cgroup under `root`, the unit's ControlGroup. Processes outside of `root` are put
+    /// directly under it.
+    pub fn build(root: &str, processes: &[UnitProcess]) -> Self {
+        let mut tree = CGroupTree {
+            path: root.to_string(),
+            ..CGroupTree::default()
+        };
+        for process in processes.iter() {
+            let relative = process
+                .cgroup_path
+                .strip_prefix(root)
+                .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+                .unwrap_or("");
+            let mut node = &mut tree;
+            for component in relative.split('/').filter(|c| !c.is_empty()) {
+                let path = format!("{}/{}", node.path.trim_end_matches('/'), component);
+                let index = match node.children.iter().position(|child| child.path == path) {
+                    Some(index) => index,
+                    None => {
+                        node.children.push(CGroupTree {
+                            path,
+                            ..CGroupTree::default()
+                        });
+                        node.children.len() - 1
+                    }
+                };
+                node = &mut node.children[index];
+            }
+            node.processes.push(process.clone());
+        }
+        tree.sort();
+        tree
+    }
+
+    /// Every process in this cgroup and below.
+    pub fn all_processes(&self) -> Vec<&UnitProcess> {
+        let mut processes: Vec<&UnitProcess> = self.processes.iter().collect();
+        for child in self.children.iter() {
+            processes.extend(child.all_processes());
+        }
+        processes
+    }
+
+    fn sort(&mut self) {
+        self.processes.sort_by_key(|process| process.pid);
+        self.children.sort_by(|a, b| a.path.cmp(&b.path));
+        for child in self.children.iter_mut() {
+            child.sort();
+        }
+    }
+
+    fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    // Processes first, then sub-cgroups, each prefixed with the branches leading to it.
+    fn write_entries(&self, f: &mut fmt::Formatter<'_>, prefix: &str) -> fmt::Result {
+        let count = self.processes.len() + self.children.len();
+        for (i, process) in self.processes.iter().enumerate() {
+            let branch = if i + 1 == count { "└─" } else { "├─" };
+            writeln!(f, "{}{}{} {}", prefix, branch, process.pid, process.cmdline)?;
+        }
+        for (i, child) in self.children.iter().enumerate() {
+            let last = self.processes.len() + i + 1 == count;
+            writeln!(f, "{}{}{}", prefix, if last { "└─" } else { "├─" }, child.name())?;
+            child.write_entries(f, &format!("{}{}", prefix, if last { "  " } else { "│ " }))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CGroupTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.path)?;
+        self.write_entries(f, "")
+    }
+}
+
+impl SystemdManager {
+    /// Processes in the cgroup of `name` and its sub-cgroups, via the Manager's GetUnitProcesses.
+    pub async fn unit_processes(&self, name: &str) -> Result<Vec<UnitProcess>, dbus::MethodErr> {
+        let processes = self.get_unit_processes(name).await?;
+        Ok(processes.into_iter().map(UnitProcess::from).collect())
+    }
+
+    /// Same as `unit_processes`, but through GetProcesses on the unit's own object; only units with a cgroup
+    /// (services, sockets, mounts, swaps, scopes and slices) have it.
+    pub async fn unit_object_processes(&self, name: &str) -> Result<Vec<UnitProcess>, dbus::MethodErr> {
+        let interface = match cgroup_interface(name) {
+            Some(interface) => interface,
+            None => return Err(dbus::MethodErr::invalid_arg(&format!("{} has no cgroup", name))),
+        };
+        match DbusConnectionManager::make_dbus_proxy(
+            SYSTEMD.service.into(),
+            unit_object_path(name).to_string(),
+            &self.connection_pool,
+        )
+        .await
+        {
+            Ok(proxy) => match proxy.method_call(interface, "GetProcesses", ()).await {
+                Ok((processes,)) => {
+                    let processes: Vec<UnitProcessDto> = processes;
+                    Ok(processes.into_iter().map(UnitProcess::from).collect())
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
+    }
+
+    /// The processes of `name` grouped by sub-cgroup under its ControlGroup.
+    pub async fn unit_process_tree(&self, name: &str) -> Result<CGroupTree, dbus::MethodErr> {
+        let interface = match cgroup_interface(name) {
+            Some(interface) => interface,
+            None => return Err(dbus::MethodErr::invalid_arg(&format!("{} has no cgroup", name))),
+        };
+        let properties = self.get_unit_properties(name, interface).await?;
+        let root = property_str(&properties, "ControlGroup")
+            .unwrap_or_default()
+            .to_string();
+        let processes = self.unit_processes(name).await?;
+        Ok(CGroupTree::build(&root, &processes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CGroupTree, UnitProcess};
+
+    fn process(cgroup_path: &str, pid: u32, cmdline: &str) -> UnitProcess {
+        UnitProcess {
+            cgroup_path: cgroup_path.to_string(),
+            pid,
+            cmdline: cmdline.to_string(),
+        }
+    }
+
+    #[test]
+    fn groups_processes_by_sub_cgroup() {
+        let root = "/system.slice/supervisor.service";
+        let processes = vec![
+            process("/system.slice/supervisor.service/workers/b", 2002, "worker b"),
+            process("/system.slice/supervisor.service/supervisor", 1000, "supervisor"),
+            process("/system.slice/supervisor.service", 999, "stray"),
+            process("/system.slice/supervisor.service/workers/a", 2001, "worker a"),
+            process("/system.slice/supervisor.service/workers/a", 2000, "worker a"),
+        ];
+        let tree = CGroupTree::build(root, &processes);
+
+        let expected = "\
+/system.slice/supervisor.service
+├─999 stray
+├─supervisor
+│ └─1000 supervisor
+└─workers
+  ├─a
+  │ ├─2000 worker a
+  │ └─2001 worker a
+  └─b
+    └─2002 worker b
+";
+        assert_eq!(tree.to_string(), expected);
+        assert_eq!(tree.children[1].path, "/system.slice/supervisor.service/workers");
+        assert_eq!(tree.all_processes().len(), 5);
+    }
+}
@@ -0,0 +1,57 @@
+use dbus::arg;
+
+pub(crate) fn property_str<'a>(properties: &'a arg::PropMap, name: &str) -> Option<&'a str> {
+    properties.get(name).and_then(|v| v.0.as_str())
+}
+
+pub(crate) fn property_bool(properties: &arg::PropMap, name: &str) -> Option<bool> {
+    properties.get(name).and_then(|v| v.0.as_i64()).map(|v| v != 0)
+}
+
+pub(crate) fn property_i64(properties: &arg::PropMap, name: &str) -> Option<i64> {
+    properties.get(name).and_then(|v| v.0.as_i64())
+}
+
+pub(crate) fn property_strings(properties: &arg::PropMap, name: &str) -> Vec<String> {
+    match properties.get(name).and_then(|v| v.0.as_iter()) {
+        Some(values) => values.filter_map(|v| v.as_str().map(String::from)).collect(),
+        None => vec![],
+    }
+}
+
+// systemd reports counters it doesn't account as u64::MAX; 0 is a real reading (no IO, no tasks).
+pub(crate) fn property_u64(properties: &arg::PropMap, name: &str) -> Option<u64> {
+    properties
+        .get(name)
+        .and_then(|v| v.0.as_u64())
+        .filter(|v| *v != u64::MAX)
+}
+
+// Timestamps that haven't happened yet are 0 (or u64::MAX for timers that never elapse).
+pub(crate) fn property_timestamp(properties: &arg::PropMap, name: &str) -> Option<u64> {
+    property_u64(properties, name).filter(|v| *v != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::{PropMap, RefArg, Variant};
+
+    use super::{property_timestamp, property_u64};
+
+    #[test]
+    fn zero_counters_are_readings() {
+        let mut properties = PropMap::new();
+        properties.insert("IOReadBytes".to_string(), Variant(Box::new(0u64) as Box<dyn RefArg>));
+        properties.insert(
+            "IOWriteBytes".to_string(),
+            Variant(Box::new(u64::MAX) as Box<dyn RefArg>),
+        );
+        properties.insert(
+            "ExecMainExitTimestampMonotonic".to_string(),
+            Variant(Box::new(0u64) as Box<dyn RefArg>),
+        );
+        assert_eq!(property_u64(&properties, "IOReadBytes"), Some(0));
+        assert_eq!(property_u64(&properties, "IOWriteBytes"), None);
+        assert_eq!(property_timestamp(&properties, "ExecMainExitTimestampMonotonic"), None);
+    }
+}
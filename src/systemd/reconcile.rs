@@ -0,0 +1,406 @@
+use std::{collections::BTreeMap, fmt};
+use tracing::debug;
+
+use super::{Mode, Systemd1Manager, SystemdManager, SystemdUnitStatus};
+
+// Unit file states `systemctl is-enabled` succeeds for that enabling can't or needn't change.
+pub(crate) const ENABLED_STATES: &[&str] = &["enabled", "static", "indirect", "alias", "generated", "transient"];
+const DISABLE_STATES: &[&str] = &["enabled", "enabled-runtime", "linked", "linked-runtime"];
+pub(crate) const RUNNING_STATES: &[&str] = &["active", "activating", "reloading"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum UnitFileGoal {
+    Enabled,
+    Disabled,
+    /// Masked units are stopped too, unless the goal says otherwise.
+    Masked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ActiveGoal {
+    Active,
+    /// Active, and restarted if it already is (e.g. its configuration changed).
+    Restarted,
+    Stopped,
+}
+
+/// What one unit should look like; `None` leaves that side of it alone.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitGoal {
+    pub unit_file: Option<UnitFileGoal>,
+    pub active: Option<ActiveGoal>,
+}
+
+/// The units to converge and what each should look like.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DesiredState {
+    pub units: BTreeMap<String, UnitGoal>,
+}
+
+impl DesiredState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enabled(self, name: &str) -> Self {
+        self.unit_file(name, UnitFileGoal::Enabled)
+    }
+
+    pub fn disabled(self, name: &str) -> Self {
+        self.unit_file(name, UnitFileGoal::Disabled)
+    }
+
+    pub fn masked(self, name: &str) -> Self {
+        self.unit_file(name, UnitFileGoal::Masked)
+    }
+
+    pub fn active(self, name: &str) -> Self {
+        self.active_state(name, ActiveGoal::Active)
+    }
+
+    pub fn restarted(self, name: &str) -> Self {
+        self.active_state(name, ActiveGoal::Restarted)
+    }
+
+    pub fn stopped(self, name: &str) -> Self {
+        self.active_state(name, ActiveGoal::Stopped)
+    }
+
+    fn unit_file(mut self, name: &str, goal: UnitFileGoal) -> Self {
+        self.units.entry(name.to_string()).or_default().unit_file = Some(goal);
+        self
+    }
+
+    fn active_state(mut self, name: &str, goal: ActiveGoal) -> Self {
+        self.units.entry(name.to_string()).or_default().active = Some(goal);
+        self
+    }
+}
+
+/// Actions in the order they're applied: unit file changes, then stops, then starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ActionKind {
+    Unmask,
+    Enable,
+    Disable,
+    Mask,
+    Stop,
+    Start,
+    Restart,
+}
+
+impl ActionKind {
+    fn changes_unit_file(self) -> bool {
+        self < ActionKind::Stop
+    }
+}
+
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ActionKind::Unmask => "unmask",
+            ActionKind::Enable => "enable",
+            ActionKind::Disable => "disable",
+            ActionKind::Mask => "mask",
+            ActionKind::Stop => "stop",
+            ActionKind::Start => "start",
+            ActionKind::Restart => "restart",
+        })
+    }
+}
+
+/// One step of a plan, with the state it was planned from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Action {
+    pub kind: ActionKind,
+    pub unit: String,
+    /// The unit file state or active state the action changes.
+    pub current: String,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} (currently {})", self.kind, self.unit, self.current)
+    }
+}
+
+/// The actions converging on a desired state. Displays one action per line in a stable order, so plans of
+/// successive dry runs can be diffed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Plan {
+    pub actions: Vec<Action>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for action in self.actions.iter() {
+            writeln!(f, "{}", action)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ActionResult {
+    pub action: Action,
+    /// Job actions fail unless their job finishes with `done`.
+    pub result: Result<(), dbus::MethodErr>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Reconciliation {
+    pub plan: Plan,
+    /// One per action of the plan in order; empty for a dry run.
+    pub results: Vec<ActionResult>,
+}
+
+impl Reconciliation {
+    pub fn succeeded(&self) -> bool {
+        self.results.iter().all(|r| r.result.is_ok())
+    }
+}
+
+/// The actions taking one unit from its `unit_file_state` and `active_state` to `goal`.
+fn plan_unit(name: &str, goal: &UnitGoal, unit_file_state: &str, active_state: &str) -> Vec<Action> {
+    let action = |kind, current: &str| Action {
+        kind,
+        unit: name.to_string(),
+        current: current.to_string(),
+    };
+    let mut actions = vec![];
+    let masked = unit_file_state.starts_with("masked");
+    match goal.unit_file {
+        Some(UnitFileGoal::Enabled) if masked => {
+            actions.push(action(ActionKind::Unmask, unit_file_state));
+            actions.push(action(ActionKind::Enable, unit_file_state));
+        }
+        Some(UnitFileGoal::Enabled) if !ENABLED_STATES.contains(&unit_file_state) => {
+            actions.push(action(ActionKind::Enable, unit_file_state))
+        }
+        Some(UnitFileGoal::Disabled) if masked => actions.push(action(ActionKind::Unmask, unit_file_state)),
+        Some(UnitFileGoal::Disabled) if DISABLE_STATES.contains(&unit_file_state) => {
+            actions.push(action(ActionKind::Disable, unit_file_state))
+        }
+        Some(UnitFileGoal::Masked) if !masked => actions.push(action(ActionKind::Mask, unit_file_state)),
+        _ => {}
+    }
+    let running = RUNNING_STATES.contains(&active_state);
+    let active_goal = match (goal.active, goal.unit_file) {
+        (None, Some(UnitFileGoal::Masked)) => Some(ActiveGoal::Stopped),
+        (active, _) => active,
+    };
+    match active_goal {
+        Some(ActiveGoal::Active) if !running => actions.push(action(ActionKind::Start, active_state)),
+        Some(ActiveGoal::Restarted) if running => actions.push(action(ActionKind::Restart, active_state)),
+        Some(ActiveGoal::Restarted) => actions.push(action(ActionKind::Start, active_state)),
+        Some(ActiveGoal::Stopped) if running => actions.push(action(ActionKind::Stop, active_state)),
+        _ => {}
+    }
+    actions
+}
+
+impl SystemdManager {
+    /// Converges units on `desired`: reads their unit file and active states, plans the actions needed and,
+    /// unless `dry_run`, applies them. Unit file changes go first and are followed by a daemon reload; a failed
+    /// action doesn't stop the others. If the reload fails, the job actions aren't run and fail with its error.
+    pub async fn reconcile(&self, desired: &DesiredState, dry_run: bool) -> Result<Reconciliation, dbus::MethodErr> {
+        for (name, goal) in desired.units.iter() {
+            let active = matches!(goal.active, Some(ActiveGoal::Active) | Some(ActiveGoal::Restarted));
+            if active && goal.unit_file == Some(UnitFileGoal::Masked) {
+                return Err(dbus::MethodErr::invalid_arg(&format!(
+                    "{} can't be both masked and active",
+                    name
+                )));
+            }
+        }
+        let plan = self.plan(desired).await?;
+        let mut reconciliation = Reconciliation { plan, results: vec![] };
+        if dry_run {
+            return Ok(reconciliation);
+        }
+
+        let (unit_file_actions, job_actions): (Vec<Action>, Vec<Action>) = reconciliation
+            .plan
+            .actions
+            .iter()
+            .cloned()
+            .partition(|action| action.kind.changes_unit_file());
+        for action in unit_file_actions.iter() {
+            let result = self.apply(action).await;
+            reconciliation.results.push(ActionResult {
+                action: action.clone(),
+                result,
+            });
+        }
+        // Jobs should see the changed unit files.
+        let reloaded = if unit_file_actions.is_empty() {
+            Ok(())
+        } else {
+            self.reload().await
+        };
+        for action in job_actions.into_iter() {
+            let result = match &reloaded {
+                Ok(()) => self.apply(&action).await,
+                Err(e) => Err(dbus::MethodErr::failed(&format!(
+                    "not run, daemon reload failed: {}",
+                    e.description()
+                ))),
+            };
+            reconciliation.results.push(ActionResult { action, result });
+        }
+        Ok(reconciliation)
+    }
+
+    async fn plan(&self, desired: &DesiredState) -> Result<Plan, dbus::MethodErr> {
+        let names: Vec<&str> = desired.units.keys().map(String::as_str).collect();
+        // ListUnitsByNames loads units from disk, so stopped units nothing depends on are included.
+        let active_states: BTreeMap<String, String> = self
+            .list_units_by_names(names)
+            .await?
+            .into_iter()
+            .map(SystemdUnitStatus::from)
+            .map(|unit| (unit.name, unit.active))
+            .collect();
+
+        let mut actions = vec![];
+        for (name, goal) in desired.units.iter() {
+            let unit_file_state = match goal.unit_file {
+                Some(_) => match self.get_unit_file_state(name).await {
+                    Ok(state) => state,
+                    Err(e) => {
+                        debug!("{}: {:?}", name, e);
+                        "not-found".to_string()
+                    }
+                },
+                None => String::new(),
+            };
+            let active_state = active_states.get(name).map(String::as_str).unwrap_or("inactive");
+            actions.extend(plan_unit(name, goal, &unit_file_state, active_state));
+        }
+        // Stable sort: each kind in unit order, and an unmask before the enable of the same unit.
+        actions.sort_by_key(|action| action.kind);
+        Ok(Plan { actions })
+    }
+
+    async fn apply(&self, action: &Action) -> Result<(), dbus::MethodErr> {
+        let unit = action.unit.as_str();
+        let mode = &Mode::Replace;
+        let job_result = match action.kind {
+            ActionKind::Unmask => return self.unmask_unit_files(vec![unit], false).await.map(|_| ()),
+            ActionKind::Enable => return self.enable_unit_files(vec![unit], false, false).await.map(|_| ()),
+            ActionKind::Disable => return self.disable_unit_files(vec![unit], false).await.map(|_| ()),
+            ActionKind::Mask => return self.mask_unit_files(vec![unit], false, false).await.map(|_| ()),
+            ActionKind::Stop => self.await_job(self.stop_unit(unit, mode)).await?,
+            ActionKind::Start => self.await_job(self.start_unit(unit, mode)).await?,
+            ActionKind::Restart => self.await_job(self.restart_unit(unit, mode)).await?,
+        };
+        if job_result == "done" {
+            Ok(())
+        } else {
+            Err(dbus::MethodErr::failed(&format!("{} job {}", action.kind, job_result)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plan_unit, Action, ActionKind, DesiredState, Plan};
+
+    fn plan(desired: &DesiredState, states: &[(&str, &str, &str)]) -> Plan {
+        let mut actions = vec![];
+        for (name, unit_file_state, active_state) in states.iter() {
+            actions.extend(plan_unit(name, &desired.units[*name], unit_file_state, active_state));
+        }
+        actions.sort_by_key(|action| action.kind);
+        Plan { actions }
+    }
+
+    #[test]
+    fn plans_only_what_differs() {
+        let desired = DesiredState::new()
+            .enabled("web.service")
+            .active("web.service")
+            .enabled("db.service")
+            .restarted("db.service")
+            .masked("legacy.service")
+            .disabled("cron.timer")
+            .stopped("cron.timer");
+        let plan = plan(
+            &desired,
+            &[
+                ("web.service", "masked", "inactive"),
+                ("db.service", "enabled", "active"),
+                ("legacy.service", "enabled", "active"),
+                ("cron.timer", "disabled", "inactive"),
+            ],
+        );
+        assert_eq!(
+            plan.to_string(),
+            "unmask web.service (currently masked)\n\
+             enable web.service (currently masked)\n\
+             mask legacy.service (currently enabled)\n\
+             stop legacy.service (currently active)\n\
+             start web.service (currently inactive)\n\
+             restart db.service (currently active)\n"
+        );
+    }
+
+    #[test]
+    fn converged_units_need_nothing() {
+        let desired = DesiredState::new()
+            .enabled("a.service")
+            .active("a.service")
+            .enabled("b.service")
+            .disabled("c.service")
+            .stopped("c.service");
+        let plan = plan(
+            &desired,
+            &[
+                ("a.service", "enabled", "active"),
+                ("b.service", "static", "inactive"),
+                ("c.service", "disabled", "failed"),
+            ],
+        );
+        assert!(plan.is_empty());
+
+        let actions = plan_unit(
+            "d.service",
+            &DesiredState::new().restarted("d.service").units["d.service"],
+            "",
+            "failed",
+        );
+        assert_eq!(
+            actions,
+            vec![Action {
+                kind: ActionKind::Start,
+                unit: "d.service".to_string(),
+                current: "failed".to_string(),
+            }]
+        );
+    }
+}
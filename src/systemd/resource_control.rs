@@ -0,0 +1,537 @@
+use dbus::arg::{self, RefArg, Variant};
+use std::{convert::TryFrom, fmt};
+
+use super::{cgroup_interface, PropertyArgs, Systemd1Manager, SystemdManager};
+
+// Scaled limits (MemoryMax=50%) are sent as a fraction of this, like systemctl does.
+const SCALE_MAX: u64 = u32::MAX as u64;
+// Highest CPU index systemd accepts in a CPU set.
+const CPU_SET_MAX: u32 = 8192;
+
+/// Resource-control settings to change on a running unit, written the way `systemctl set-property` takes them:
+/// `CPUQuota=150%`, `MemoryMax=512M`, `TasksMax=infinity`, `AllowedCPUs=0-3,8`.
+///
+/// Setters only record values; `build` parses and validates them, and `SystemdManager::set_resource_control`
+/// applies and verifies them.
+#[derive(Clone, Debug, Default)]
+pub struct ResourceControl {
+    settings: Vec<(&'static str, String)>,
+    device_limits: Vec<(&'static str, String, String)>,
+}
+
+impl ResourceControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// CPU time relative to one CPU, like `150%`; `infinity` removes the quota.
+    pub fn cpu_quota(self, quota: &str) -> Self {
+        self.setting("CPUQuota", quota)
+    }
+
+    /// 1 to 10000.
+    pub fn cpu_weight(self, weight: u64) -> Self {
+        self.setting("CPUWeight", &weight.to_string())
+    }
+
+    /// Bytes with an optional K, M, G, T, P or E suffix (base 1024), a percentage of physical memory, or
+    /// `infinity`.
+    pub fn memory_max(self, limit: &str) -> Self {
+        self.setting("MemoryMax", limit)
+    }
+
+    pub fn memory_high(self, limit: &str) -> Self {
+        self.setting("MemoryHigh", limit)
+    }
+
+    pub fn memory_low(self, limit: &str) -> Self {
+        self.setting("MemoryLow", limit)
+    }
+
+    /// A number of tasks, a percentage of the system's limit, or `infinity`.
+    pub fn tasks_max(self, limit: &str) -> Self {
+        self.setting("TasksMax", limit)
+    }
+
+    /// 1 to 10000.
+    pub fn io_weight(self, weight: u64) -> Self {
+        self.setting("IOWeight", &weight.to_string())
+    }
+
+    /// Bytes per second with an optional K, M, G or T suffix (base 1000), or `infinity`, for the block device
+    /// at `device`.
+    pub fn io_read_bandwidth_max(self, device: &str, limit: &str) -> Self {
+        self.device_limit("IOReadBandwidthMax", device, limit)
+    }
+
+    pub fn io_write_bandwidth_max(self, device: &str, limit: &str) -> Self {
+        self.device_limit("IOWriteBandwidthMax", device, limit)
+    }
+
+    /// Operations per second, with the same suffixes as bandwidth limits.
+    pub fn io_read_iops_max(self, device: &str, limit: &str) -> Self {
+        self.device_limit("IOReadIOPSMax", device, limit)
+    }
+
+    pub fn io_write_iops_max(self, device: &str, limit: &str) -> Self {
+        self.device_limit("IOWriteIOPSMax", device, limit)
+    }
+
+    /// CPU indices and ranges separated by commas or spaces, like `0-3,8`.
+    pub fn allowed_cpus(self, cpus: &str) -> Self {
+        self.setting("AllowedCPUs", cpus)
+    }
+
+    fn setting(mut self, name: &'static str, value: &str) -> Self {
+        self.settings.retain(|(setting, _)| *setting != name);
+        self.settings.push((name, value.trim().to_string()));
+        self
+    }
+
+    fn device_limit(mut self, name: &'static str, device: &str, limit: &str) -> Self {
+        self.device_limits
+            .push((name, device.to_string(), limit.trim().to_string()));
+        self
+    }
+
+    /// Parses every setting into the property systemd expects.
+    pub fn build(self) -> Result<ResourceProperties, dbus::MethodErr> {
+        let mut properties: Vec<(String, ResourceValue)> = vec![];
+        for (name, value) in self.settings.iter() {
+            let invalid = |reason: &str| invalid(format!("{}={}: {}", name, value, reason));
+            let property = match *name {
+                "CPUQuota" => {
+                    let usec = if value == "infinity" {
+                        u64::MAX
+                    } else {
+                        match parse_percent(value) {
+                            Some(percent) if percent > 0.0 => (percent * 10_000.0).round() as u64,
+                            _ => return Err(invalid("expected a percentage greater than 0%")),
+                        }
+                    };
+                    ("CPUQuotaPerSecUSec".to_string(), ResourceValue::U64(usec))
+                }
+                "CPUWeight" | "IOWeight" => match value.parse::<u64>() {
+                    Ok(weight) if (1..=10_000).contains(&weight) => (name.to_string(), ResourceValue::U64(weight)),
+                    _ => return Err(invalid("expected a weight from 1 to 10000")),
+                },
+                "MemoryMax" | "MemoryHigh" | "MemoryLow" | "TasksMax" => {
+                    let base = if *name == "TasksMax" { 1000 } else { 1024 };
+                    if value == "infinity" {
+                        (name.to_string(), ResourceValue::U64(u64::MAX))
+                    } else if let Some(percent) = parse_percent(value) {
+                        if !(0.0..=100.0).contains(&percent) {
+                            return Err(invalid("percentage must be between 0% and 100%"));
+                        }
+                        let scale = (percent / 100.0 * SCALE_MAX as f64).round() as u32;
+                        (format!("{}Scale", name), ResourceValue::U32(scale))
+                    } else {
+                        let parsed = if *name == "TasksMax" {
+                            value.parse().ok()
+                        } else {
+                            parse_size(value, base)
+                        };
+                        match parsed {
+                            Some(amount) => (name.to_string(), ResourceValue::U64(amount)),
+                            None => return Err(invalid("expected a size, a percentage or infinity")),
+                        }
+                    }
+                }
+                "AllowedCPUs" => match parse_cpu_set(value) {
+                    Some(mask) => (name.to_string(), ResourceValue::Bytes(mask)),
+                    None => return Err(invalid("expected CPU indices or ranges like 0-3,8")),
+                },
+                _ => unreachable!(),
+            };
+            properties.push(property);
+        }
+
+        for (name, device, limit) in self.device_limits.iter() {
+            if !device.starts_with('/') {
+                return Err(invalid(format!("{}: device {} must be an absolute path", name, device)));
+            }
+            let limit = match limit.as_str() {
+                "infinity" => u64::MAX,
+                limit => match parse_size(limit, 1000) {
+                    Some(limit) if limit > 0 => limit,
+                    _ => {
+                        return Err(invalid(format!(
+                            "{}={} {}: expected a positive limit",
+                            name, device, limit
+                        )))
+                    }
+                },
+            };
+            match properties.iter_mut().find(|(property, _)| property == name) {
+                Some((_, ResourceValue::Devices(devices))) => devices.push((device.clone(), limit)),
+                _ => properties.push((name.to_string(), ResourceValue::Devices(vec![(device.clone(), limit)]))),
+            }
+        }
+        Ok(ResourceProperties { properties })
+    }
+}
+
+/// A parsed resource-control value, in the D-Bus type of its property.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResourceValue {
+    U64(u64),
+    U32(u32),
+    /// Per-device limits, a(st).
+    Devices(Vec<(String, u64)>),
+    /// A CPU bitmask, ay.
+    Bytes(Vec<u8>),
+}
+
+impl ResourceValue {
+    fn boxed(&self) -> Box<dyn RefArg> {
+        match self {
+            ResourceValue::U64(v) => Box::new(*v),
+            ResourceValue::U32(v) => Box::new(*v),
+            ResourceValue::Devices(v) => Box::new(v.clone()),
+            ResourceValue::Bytes(v) => Box::new(v.clone()),
+        }
+    }
+}
+
+impl fmt::Display for ResourceValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limit = |value: u64| match value {
+            u64::MAX => "infinity".to_string(),
+            value => value.to_string(),
+        };
+        match self {
+            ResourceValue::U64(v) => f.write_str(&limit(*v)),
+            ResourceValue::U32(v) => write!(f, "{}", v),
+            ResourceValue::Devices(devices) => {
+                let devices: Vec<String> = devices
+                    .iter()
+                    .map(|(device, value)| format!("{} {}", device, limit(*value)))
+                    .collect();
+                f.write_str(&devices.join(","))
+            }
+            ResourceValue::Bytes(mask) => {
+                for byte in mask.iter() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Validated resource-control properties, ready for SetUnitProperties.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ResourceProperties {
+    pub properties: Vec<(String, ResourceValue)>,
+}
+
+impl ResourceProperties {
+    pub fn as_args(&self) -> PropertyArgs<'_> {
+        self.properties
+            .iter()
+            .map(|(name, value)| (name.as_str(), Variant(value.boxed())))
+            .collect()
+    }
+
+    /// Names of the properties whose value in `current` (as read from the unit) differs from what was set.
+    /// Percentages are applied by systemd against the machine's limits and can't be compared, so they're skipped.
+    pub fn mismatches(&self, current: &arg::PropMap) -> Vec<String> {
+        self.properties
+            .iter()
+            .filter(|(name, expected)| {
+                let actual = current.get(name.as_str()).map(|v| &v.0);
+                match (expected, actual) {
+                    (ResourceValue::U32(_), _) => false,
+                    (ResourceValue::U64(expected), Some(actual)) => actual.as_u64() != Some(*expected),
+                    (ResourceValue::Devices(expected), Some(actual)) => {
+                        let actual = device_limits(actual.as_ref());
+                        !expected.iter().all(|limit| actual.contains(limit))
+                    }
+                    (ResourceValue::Bytes(expected), Some(actual)) => {
+                        trim_mask(&cpu_mask(actual.as_ref())) != trim_mask(expected)
+                    }
+                    (_, None) => true,
+                }
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// The values these properties have in `current` (as read from the unit), to set them back later. Scaled
+    /// limits are read back as absolute ones, and devices limited here but not in `current` get `infinity`.
+    pub(crate) fn previous(&self, current: &arg::PropMap) -> ResourceProperties {
+        let mut properties = vec![];
+        for (name, value) in self.properties.iter() {
+            let name = match value {
+                ResourceValue::U32(_) => name.strip_suffix("Scale").unwrap_or(name),
+                _ => name.as_str(),
+            };
+            let actual = match current.get(name) {
+                Some(actual) => actual.0.as_ref(),
+                None => continue,
+            };
+            let previous = match value {
+                ResourceValue::U64(_) | ResourceValue::U32(_) => match actual.as_u64() {
+                    Some(actual) => ResourceValue::U64(actual),
+                    None => continue,
+                },
+                ResourceValue::Devices(devices) => {
+                    let mut limits = device_limits(actual);
+                    for (device, _) in devices.iter() {
+                        if !limits.iter().any(|(limited, _)| limited == device) {
+                            limits.push((device.clone(), u64::MAX));
+                        }
+                    }
+                    ResourceValue::Devices(limits)
+                }
+                ResourceValue::Bytes(_) => ResourceValue::Bytes(cpu_mask(actual)),
+            };
+            properties.push((name.to_string(), previous));
+        }
+        ResourceProperties { properties }
+    }
+}
+
+impl fmt::Display for ResourceProperties {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.properties.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl SystemdManager {
+    /// Applies `control` to the running unit `name`, only until the next reboot if `runtime` is set and
+    /// persistently (as a drop-in) otherwise, then reads the unit's properties back to check they took effect.
+    pub async fn set_resource_control(
+        &self,
+        name: &str,
+        control: ResourceControl,
+        runtime: bool,
+    ) -> Result<(), dbus::MethodErr> {
+        let properties = control.build()?;
+        self.set_resource_properties(name, &properties, runtime).await
+    }
+
+    /// [`set_resource_control`](Self::set_resource_control) with properties already built.
+    pub(crate) async fn set_resource_properties(
+        &self,
+        name: &str,
+        properties: &ResourceProperties,
+        runtime: bool,
+    ) -> Result<(), dbus::MethodErr> {
+        let interface = match cgroup_interface(name) {
+            Some(interface) => interface,
+            None => return Err(invalid(format!("{} has no cgroup to control", name))),
+        };
+        self.set_unit_properties(name, runtime, properties.as_args()).await?;
+
+        let current = self.get_unit_properties(name, interface).await?;
+        let mismatches = properties.mismatches(&current);
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(dbus::MethodErr::failed(&format!(
+                "{} did not take {}",
+                name,
+                mismatches.join(", ")
+            )))
+        }
+    }
+}
+
+fn device_limits(value: &dyn RefArg) -> Vec<(String, u64)> {
+    let mut limits = vec![];
+    if let Some(entries) = value.as_iter() {
+        for entry in entries {
+            if let Some(mut fields) = entry.as_iter() {
+                if let (Some(device), Some(limit)) = (fields.next(), fields.next()) {
+                    if let (Some(device), Some(limit)) = (device.as_str(), limit.as_u64()) {
+                        limits.push((device.to_string(), limit));
+                    }
+                }
+            }
+        }
+    }
+    limits
+}
+
+fn cpu_mask(value: &dyn RefArg) -> Vec<u8> {
+    match value.as_iter() {
+        Some(bytes) => bytes.filter_map(|b| b.as_u64()).map(|b| b as u8).collect(),
+        None => vec![],
+    }
+}
+
+fn trim_mask(mask: &[u8]) -> &[u8] {
+    let len = mask.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    &mask[..len]
+}
+
+fn parse_percent(value: &str) -> Option<f64> {
+    value
+        .strip_suffix('%')
+        .and_then(|number| number.parse::<f64>().ok())
+        .filter(|percent| percent.is_finite())
+}
+
+/// Parses a size like systemd's parse_size(): a number, optionally fractional, with an optional B, K, M, G, T,
+/// P or E suffix in powers of `base` (1024 for memory, 1000 for IO limits).
+pub fn parse_size(value: &str, base: u64) -> Option<u64> {
+    let suffixes = ["B", "K", "M", "G", "T", "P", "E"];
+    let (number, exponent) = match suffixes.iter().position(|suffix| value.ends_with(suffix)) {
+        Some(exponent) => (&value[..value.len() - 1], exponent as u32),
+        None => (value, 0),
+    };
+    let factor = base.checked_pow(exponent)?;
+    let (whole, fraction) = match number.find('.') {
+        Some(dot) => (&number[..dot], &number[dot + 1..]),
+        None => (number, ""),
+    };
+    if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let size = whole.parse::<u64>().ok()?.checked_mul(factor)?;
+    // Like systemd, digits of the fraction beyond what the unit can represent are dropped.
+    let fraction = &fraction[..fraction.len().min(18)];
+    if fraction.is_empty() {
+        return Some(size);
+    }
+    let numerator = fraction.parse::<u128>().ok()? * u128::from(factor);
+    let part = numerator / 10u128.pow(fraction.len() as u32);
+    size.checked_add(u64::try_from(part).ok()?)
+}
+
+// A CPU list like `0-3,8` as the little-endian bitmask systemd sends as ay.
+fn parse_cpu_set(value: &str) -> Option<Vec<u8>> {
+    let mut mask: Vec<u8> = vec![];
+    let items: Vec<&str> = value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|i| !i.is_empty())
+        .collect();
+    if items.is_empty() {
+        return None;
+    }
+    for item in items {
+        let (start, end) = match item.split_once('-') {
+            Some((start, end)) => (start.parse::<u32>().ok()?, end.parse::<u32>().ok()?),
+            None => {
+                let cpu = item.parse::<u32>().ok()?;
+                (cpu, cpu)
+            }
+        };
+        if start > end || end >= CPU_SET_MAX {
+            return None;
+        }
+        for cpu in start..=end {
+            let byte = (cpu / 8) as usize;
+            if mask.len() <= byte {
+                mask.resize(byte + 1, 0);
+            }
+            mask[byte] |= 1 << (cpu % 8);
+        }
+    }
+    Some(mask)
+}
+
+fn invalid<T: std::fmt::Display>(message: T) -> dbus::MethodErr {
+    dbus::MethodErr::invalid_arg(&message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::{PropMap, RefArg, Variant};
+
+    use super::{parse_size, ResourceControl, ResourceValue};
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512", 1024), Some(512));
+        assert_eq!(parse_size("512M", 1024), Some(512 << 20));
+        assert_eq!(parse_size("1.5G", 1024), Some(3 << 29));
+        assert_eq!(parse_size("10M", 1000), Some(10_000_000));
+        assert_eq!(parse_size("10 parsecs", 1024), None);
+        assert_eq!(parse_size("M", 1024), None);
+    }
+
+    #[test]
+    fn builds_typed_properties() {
+        let properties = ResourceControl::new()
+            .cpu_quota("150%")
+            .cpu_weight(200)
+            .memory_max("512M")
+            .memory_high("50%")
+            .tasks_max("infinity")
+            .io_read_bandwidth_max("/dev/sda", "10M")
+            .io_read_bandwidth_max("/dev/sdb", "20M")
+            .allowed_cpus("0-3,8")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            properties.properties,
+            vec![
+                ("CPUQuotaPerSecUSec".to_string(), ResourceValue::U64(1_500_000)),
+                ("CPUWeight".to_string(), ResourceValue::U64(200)),
+                ("MemoryMax".to_string(), ResourceValue::U64(512 << 20)),
+                ("MemoryHighScale".to_string(), ResourceValue::U32(u32::MAX / 2 + 1)),
+                ("TasksMax".to_string(), ResourceValue::U64(u64::MAX)),
+                ("AllowedCPUs".to_string(), ResourceValue::Bytes(vec![0x0f, 0x01])),
+                (
+                    "IOReadBandwidthMax".to_string(),
+                    ResourceValue::Devices(vec![
+                        ("/dev/sda".to_string(), 10_000_000),
+                        ("/dev/sdb".to_string(), 20_000_000)
+                    ])
+                ),
+            ]
+        );
+
+        let signatures: Vec<String> = properties
+            .as_args()
+            .iter()
+            .map(|(name, value)| format!("{}:{}", name, value.0.signature()))
+            .collect();
+        assert!(signatures.contains(&"IOReadBandwidthMax:a(st)".to_string()));
+        assert!(signatures.contains(&"AllowedCPUs:ay".to_string()));
+        assert!(signatures.contains(&"MemoryHighScale:u".to_string()));
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert!(ResourceControl::new().cpu_quota("0%").build().is_err());
+        assert!(ResourceControl::new().cpu_quota("1.5").build().is_err());
+        assert!(ResourceControl::new().cpu_weight(0).build().is_err());
+        assert!(ResourceControl::new().memory_max("lots").build().is_err());
+        assert!(ResourceControl::new().memory_max("150%").build().is_err());
+        assert!(ResourceControl::new().allowed_cpus("3-1").build().is_err());
+        assert!(ResourceControl::new().io_write_iops_max("sda", "100").build().is_err());
+    }
+
+    #[test]
+    fn detects_settings_that_did_not_take() {
+        let properties = ResourceControl::new()
+            .memory_max("512M")
+            .tasks_max("100")
+            .allowed_cpus("0-3")
+            .build()
+            .unwrap();
+        let mut current = PropMap::new();
+        current.insert(
+            "MemoryMax".to_string(),
+            Variant(Box::new(512u64 << 20) as Box<dyn RefArg>),
+        );
+        current.insert("TasksMax".to_string(), Variant(Box::new(4915u64) as Box<dyn RefArg>));
+        current.insert(
+            "AllowedCPUs".to_string(),
+            Variant(Box::new(vec![0x0fu8, 0x00]) as Box<dyn RefArg>),
+        );
+        assert_eq!(properties.mismatches(&current), vec!["TasksMax".to_string()]);
+    }
+}
@@ -0,0 +1,265 @@
+use dbus::{arg, message::SignalArgs};
+use futures::StreamExt;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::dbus::DBusPropertiesPropertiesChanged;
+
+use super::{
+    property_i64, property_str, property_timestamp, property_u64, unit_object_path, ExecCommand, Mode, ServiceType,
+    Systemd1Manager, Systemd1ManagerJobRemoved, SystemdManager, TransientUnitBuilder, SYSTEMD_SERVICE, SYSTEMD_UNIT,
+};
+
+/// Settings for `SystemdManager::run_transient`; the `systemd-run --wait` flags we support.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RunOptions {
+    /// Unit name to use; defaults to a generated `run-r<id>.service`.
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub environment: Vec<(String, String)>,
+    pub working_directory: Option<String>,
+    pub user: Option<String>,
+    pub slice: Option<String>,
+    /// Sets CollectMode=inactive-or-failed so a failed run is unloaded too instead of being kept for inspection.
+    /// systemd may then unload a failed run before its accounting is read, leaving the CPU, memory and IO figures
+    /// of the result unset.
+    pub collect: bool,
+    /// Stop the service and give up waiting after this long.
+    pub timeout: Option<Duration>,
+}
+
+/// Outcome of a transient service run, read from its Service properties.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransientRunResult {
+    pub unit: String,
+    /// The service's Result property: success, exit-code, signal, core-dump, timeout, ...
+    pub result: String,
+    /// How the main process exited: 1 (exited), 2 (killed) or 3 (dumped), as in `siginfo_t.si_code`.
+    pub exec_main_code: i32,
+    /// Exit status when `exec_main_code` is 1, otherwise the signal number.
+    pub exec_main_status: i32,
+    pub runtime: Option<Duration>,
+    pub cpu_usage: Option<Duration>,
+    pub memory_peak: Option<u64>,
+    pub io_read_bytes: Option<u64>,
+    pub io_write_bytes: Option<u64>,
+}
+
+impl TransientRunResult {
+    pub fn success(&self) -> bool {
+        self.result == "success"
+    }
+
+    fn from_properties(unit: String, properties: &arg::PropMap) -> Self {
+        let start = property_timestamp(properties, "ExecMainStartTimestampMonotonic");
+        let exit = property_timestamp(properties, "ExecMainExitTimestampMonotonic");
+        TransientRunResult {
+            unit,
+            result: property_str(properties, "Result").unwrap_or_default().to_string(),
+            exec_main_code: property_i64(properties, "ExecMainCode").unwrap_or_default() as i32,
+            exec_main_status: property_i64(properties, "ExecMainStatus").unwrap_or_default() as i32,
+            runtime: match (start, exit) {
+                (Some(start), Some(exit)) if exit >= start => Some(Duration::from_micros(exit - start)),
+                _ => None,
+            },
+            cpu_usage: property_u64(properties, "CPUUsageNSec").map(Duration::from_nanos),
+            memory_peak: property_u64(properties, "MemoryPeak"),
+            io_read_bytes: property_u64(properties, "IOReadBytes"),
+            io_write_bytes: property_u64(properties, "IOWriteBytes"),
+        }
+    }
+}
+
+impl SystemdManager {
+    /// Runs `command` as a transient service and waits for it to finish, like `systemd-run --wait`.
+    ///
+    /// The service is created with RemainAfterExit=yes so its exit status and accounting can still be read
+    /// once the main process is gone; it is stopped afterwards. Failed runs are left loaded unless
+    /// `options.collect` is set.
+    pub async fn run_transient(
+        &self,
+        command: ExecCommand,
+        options: RunOptions,
+    ) -> Result<TransientRunResult, dbus::MethodErr> {
+        let mut unit = match &options.name {
+            Some(name) => TransientUnitBuilder::new(name),
+            None => TransientUnitBuilder::with_generated_name("service"),
+        };
+        let name = unit.name().to_string();
+        unit = unit
+            .description(options.description.as_deref().unwrap_or(&command.argv.join(" ")))
+            .exec_start(command)
+            .service_type(ServiceType::Exec)
+            .remain_after_exit(true);
+        for (key, value) in options.environment.iter() {
+            unit = unit.environment(key, value);
+        }
+        if let Some(working_directory) = &options.working_directory {
+            unit = unit.working_directory(working_directory);
+        }
+        if let Some(user) = &options.user {
+            unit = unit.user(user);
+        }
+        if let Some(slice) = &options.slice {
+            unit = unit.slice(slice);
+        }
+        if options.collect {
+            unit = unit.property("CollectMode", String::from("inactive-or-failed"));
+        }
+        let unit = unit.build()?;
+
+        let connection = self.connection().await?;
+        let changes_rule =
+            DBusPropertiesPropertiesChanged::match_rule(None, Some(&unit_object_path(&name))).static_clone();
+        let jobs_rule = Systemd1ManagerJobRemoved::match_rule(None, None).static_clone();
+        let (changes_match, mut changes) = connection
+            .add_match(changes_rule)
+            .await
+            .map_err(dbus::MethodErr::from)?
+            .stream::<DBusPropertiesPropertiesChanged>();
+        let (jobs_match, mut jobs) = connection
+            .add_match(jobs_rule)
+            .await
+            .map_err(dbus::MethodErr::from)?
+            .stream::<Systemd1ManagerJobRemoved>();
+
+        let waited = async {
+            self.subscribe().await?;
+            let job_path = unit.start(self, &Mode::Fail).await?;
+            let mut properties = arg::PropMap::new();
+            let mut poll = tokio::time::interval(Duration::from_secs(1));
+
+            while !is_finished(&properties) {
+                tokio::select! {
+                    Some((_, changed)) = changes.next() => properties.extend(changed.changed_properties),
+                    Some((_, job)) = jobs.next() => {
+                        if job.job_path == job_path && job.result != "done" {
+                            let message = format!("start job for {} finished with result {}", name, job.result);
+                            return Err(dbus::MethodErr::failed(&message));
+                        }
+                    }
+                    _ = poll.tick() => {
+                        for interface in [SYSTEMD_UNIT.interface, SYSTEMD_SERVICE.interface].iter() {
+                            if let Ok(current) = self.get_unit_properties(&name, interface).await {
+                                properties.extend(current);
+                            }
+                        }
+                    }
+                }
+            }
+            // Accounting isn't sent with PropertiesChanged; read it right away, before a failed run can be
+            // collected.
+            match self.get_unit_properties(&name, SYSTEMD_SERVICE.interface).await {
+                Ok(current) => properties.extend(current),
+                Err(e) => debug!("{}: accounting not read: {:?}", name, e),
+            }
+            Ok(properties)
+        };
+        let waited = match options.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, waited).await {
+                Ok(waited) => waited,
+                Err(_) => {
+                    let _ = self.stop_unit(&name, &Mode::Replace).await;
+                    Err(dbus::MethodErr::failed(&format!(
+                        "{} did not finish within {:?}",
+                        name, timeout
+                    )))
+                }
+            },
+            None => waited.await,
+        };
+
+        for token in [changes_match.token(), jobs_match.token()].iter() {
+            if let Err(e) = connection.remove_match(*token).await {
+                debug!("{:?}", e);
+            }
+        }
+
+        let properties = waited?;
+        if property_str(&properties, "ActiveState") == Some("active") {
+            self.stop_unit(&name, &Mode::Replace).await?;
+        }
+
+        Ok(TransientRunResult::from_properties(name, &properties))
+    }
+}
+
+// With RemainAfterExit=yes the service ends up in active/exited on success and failed otherwise.
+fn is_finished(properties: &arg::PropMap) -> bool {
+    match (
+        property_str(properties, "ActiveState"),
+        property_str(properties, "SubState"),
+    ) {
+        (Some("failed"), _) => true,
+        (Some("active"), Some("exited")) => true,
+        (Some("inactive"), _) => property_timestamp(properties, "ExecMainExitTimestampMonotonic").is_some(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::{PropMap, RefArg, Variant};
+    use std::time::Duration;
+
+    use super::{is_finished, TransientRunResult};
+
+    fn properties(entries: Vec<(&str, Box<dyn RefArg>)>) -> PropMap {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), Variant(value)))
+            .collect()
+    }
+
+    fn states(active: &str, sub: &str) -> PropMap {
+        properties(vec![
+            ("ActiveState", Box::new(active.to_string())),
+            ("SubState", Box::new(sub.to_string())),
+        ])
+    }
+
+    #[test]
+    fn runs_finish_once_exited_or_failed() {
+        assert!(is_finished(&states("active", "exited")));
+        assert!(is_finished(&states("failed", "failed")));
+        assert!(!is_finished(&states("active", "running")));
+        // Restart=on-failure passes through auto-restart; the run isn't over yet.
+        assert!(!is_finished(&states("activating", "auto-restart")));
+        assert!(!is_finished(&states("inactive", "dead")));
+
+        let mut collected = states("inactive", "dead");
+        collected.insert(
+            "ExecMainExitTimestampMonotonic".to_string(),
+            Variant(Box::new(2_000_000u64) as Box<dyn RefArg>),
+        );
+        assert!(is_finished(&collected));
+    }
+
+    #[test]
+    fn results_are_read_from_service_properties() {
+        let result = TransientRunResult::from_properties(
+            "run-u1.service".to_string(),
+            &properties(vec![
+                ("Result", Box::new("exit-code".to_string())),
+                ("ExecMainCode", Box::new(1i32)),
+                ("ExecMainStatus", Box::new(3i32)),
+                ("ExecMainStartTimestampMonotonic", Box::new(1_000_000u64)),
+                ("ExecMainExitTimestampMonotonic", Box::new(3_500_000u64)),
+                ("CPUUsageNSec", Box::new(250_000_000u64)),
+                ("MemoryPeak", Box::new(4096u64)),
+                ("IOReadBytes", Box::new(0u64)),
+                ("IOWriteBytes", Box::new(u64::MAX)),
+            ]),
+        );
+        assert_eq!(result.unit, "run-u1.service");
+        assert!(!result.success());
+        assert_eq!((result.exec_main_code, result.exec_main_status), (1, 3));
+        assert_eq!(result.runtime, Some(Duration::from_millis(2500)));
+        assert_eq!(result.cpu_usage, Some(Duration::from_millis(250)));
+        assert_eq!(result.memory_peak, Some(4096));
+        assert_eq!(result.io_read_bytes, Some(0));
+        assert_eq!(result.io_write_bytes, None);
+    }
+}
@@ -0,0 +1,84 @@
+use tokio::process::{Child, Command};
+
+use super::{unit_object_path, Mode, SystemdManager, TransientUnitBuilder};
+
+impl SystemdManager {
+    /// Creates a transient scope holding the builder's PIDs, or the current process when it has none, like
+    /// `systemd-run --scope`.
+    ///
+    /// Slice placement and resource limits come from the builder; this returns once the scope's start job is done.
+    pub async fn start_transient_scope(
+        &self,
+        scope: TransientUnitBuilder,
+    ) -> Result<dbus::Path<'static>, dbus::MethodErr> {
+        let scope = with_scope_pids(scope).build()?;
+
+        let result = self.await_job(scope.start(self, &Mode::Fail)).await?;
+        if result != "done" {
+            let message = format!("start job for {} finished with result {}", scope.name, result);
+            return Err(dbus::MethodErr::failed(&message));
+        }
+        Ok(unit_object_path(&scope.name))
+    }
+
+    /// Spawns `command` and moves it, along with any PIDs already on the builder, into a fresh scope built from
+    /// `scope`.
+    ///
+    /// The child runs before it is moved, so anything it forks in the meantime stays in our cgroup. The child is
+    /// killed if the scope can't be created.
+    pub async fn spawn_in_scope(
+        &self,
+        command: &mut Command,
+        scope: TransientUnitBuilder,
+    ) -> Result<(Child, dbus::Path<'static>), dbus::MethodErr> {
+        let mut child = command
+            .spawn()
+            .map_err(|e| dbus::MethodErr::failed(&format!("{:?}", e)))?;
+        let pid = match child.id() {
+            Some(pid) => pid,
+            None => return Err(dbus::MethodErr::failed(&"spawned process exited before it got a scope")),
+        };
+
+        match self.start_transient_scope(scope.pids(vec![pid])).await {
+            Ok(path) => Ok((child, path)),
+            Err(e) => {
+                let _ = child.start_kill();
+                Err(e)
+            }
+        }
+    }
+}
+
+fn with_scope_pids(scope: TransientUnitBuilder) -> TransientUnitBuilder {
+    if scope.has_pids() {
+        scope
+    } else {
+        scope.pids(vec![std::process::id()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::RefArg;
+
+    use super::with_scope_pids;
+    use crate::systemd::TransientUnitBuilder;
+
+    fn scope_pids(scope: TransientUnitBuilder) -> Vec<u64> {
+        let unit = with_scope_pids(scope).build().unwrap();
+        let (_, pids) = unit.properties.iter().find(|(name, _)| name == "PIDs").unwrap();
+        pids.0.as_iter().unwrap().filter_map(|pid| pid.as_u64()).collect()
+    }
+
+    #[test]
+    fn scopes_hold_the_current_process_only_without_pids() {
+        assert_eq!(
+            scope_pids(TransientUnitBuilder::new("batch.scope")),
+            vec![std::process::id() as u64]
+        );
+        assert_eq!(
+            scope_pids(TransientUnitBuilder::new("batch.scope").pids(vec![7, 8])),
+            vec![7, 8]
+        );
+    }
+}
@@ -0,0 +1,52 @@
+//! `Result<(), dbus::MethodErr>` fields as `{"Ok": null}` or `{"Err": {"name": ..., "message": ...}}`, for
+//! `#[serde(with = "super::serde_method_result")]`; `option` does the same for `Option<Result<...>>`.
+use dbus::strings::ErrorName;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Serialize, Deserialize)]
+struct MethodError {
+    name: String,
+    message: String,
+}
+
+fn to_serde(result: &Result<(), dbus::MethodErr>) -> Result<(), MethodError> {
+    result.clone().map_err(|e| MethodError {
+        name: e.errorname().to_string(),
+        message: e.description().to_string(),
+    })
+}
+
+fn from_serde<E: de::Error>(result: Result<(), MethodError>) -> Result<Result<(), dbus::MethodErr>, E> {
+    match result {
+        Ok(()) => Ok(Ok(())),
+        Err(e) => {
+            let name = ErrorName::new(e.name).map_err(de::Error::custom)?;
+            Ok(Err((name, e.message).into()))
+        }
+    }
+}
+
+pub(crate) fn serialize<S: Serializer>(result: &Result<(), dbus::MethodErr>, serializer: S) -> Result<S::Ok, S::Error> {
+    to_serde(result).serialize(serializer)
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Result<(), dbus::MethodErr>, D::Error> {
+    from_serde(Result::deserialize(deserializer)?)
+}
+
+pub(crate) mod option {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(
+        result: &Option<Result<(), dbus::MethodErr>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        result.as_ref().map(super::to_serde).serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Result<(), dbus::MethodErr>>, D::Error> {
+        Option::deserialize(deserializer)?.map(super::from_serde).transpose()
+    }
+}
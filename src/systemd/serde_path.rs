@@ -0,0 +1,10 @@
+//! `dbus::Path` fields as plain strings, for `#[serde(with = "super::serde_path")]`.
+use serde::{de, Deserialize, Deserializer, Serializer};
+
+pub(crate) fn serialize<S: Serializer>(path: &dbus::Path<'static>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(path)
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<dbus::Path<'static>, D::Error> {
+    dbus::Path::new(String::deserialize(deserializer)?).map_err(de::Error::custom)
+}
@@ -0,0 +1,274 @@
+use std::{
+    io, mem,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+};
+
+const LISTEN_PID: &str = "LISTEN_PID";
+const LISTEN_FDS: &str = "LISTEN_FDS";
+const LISTEN_FDNAMES: &str = "LISTEN_FDNAMES";
+/// The first passed file descriptor, SD_LISTEN_FDS_START.
+const LISTEN_FDS_START: RawFd = 3;
+/// The name of sockets without FileDescriptorName=, and of all sockets when LISTEN_FDNAMES isn't set.
+const UNKNOWN_NAME: &str = "unknown";
+
+/// Sockets passed by systemd to a socket-activated service, by FileDescriptorName= (the socket unit's name by
+/// default).
+///
+/// Sockets not taken are closed when this is dropped.
+#[derive(Debug, Default)]
+pub struct ListenFds {
+    fds: Vec<(String, OwnedFd)>,
+}
+
+impl ListenFds {
+    /// Takes the sockets passed to this process, like sd_listen_fds_with_names(3) with unset_environment set:
+    /// LISTEN_PID, LISTEN_FDS and LISTEN_FDNAMES are removed so child processes don't take them too, and the
+    /// sockets are made close-on-exec. Empty when the process wasn't socket-activated.
+    pub fn from_env() -> io::Result<Self> {
+        let var = |name: &str| std::env::var(name).ok();
+        let passed = passed_fds(
+            var(LISTEN_PID).as_deref(),
+            var(LISTEN_FDS).as_deref(),
+            var(LISTEN_FDNAMES).as_deref(),
+            std::process::id(),
+        );
+        for name in [LISTEN_PID, LISTEN_FDS, LISTEN_FDNAMES].iter() {
+            std::env::remove_var(name);
+        }
+        let mut fds = vec![];
+        for (fd, name) in passed?.into_iter() {
+            // SAFETY: systemd hands these descriptors to this process, and the environment naming them is gone.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            set_cloexec(&fd)?;
+            fds.push((name, fd));
+        }
+        Ok(Self { fds })
+    }
+
+    pub fn len(&self) -> usize {
+        self.fds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    /// Names of the sockets not taken yet, in the order they were passed.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fds.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Takes the first socket named `name`, without checking what it is.
+    pub fn take(&mut self, name: &str) -> Option<OwnedFd> {
+        let index = self.fds.iter().position(|(n, _)| n == name)?;
+        Some(self.fds.remove(index).1)
+    }
+
+    /// Takes every descriptor named `name`, e.g. those stored with [`store_fds`](super::store_fds) before a
+    /// restart, in the order they were passed.
+    pub fn take_all(&mut self, name: &str) -> Vec<OwnedFd> {
+        let (taken, kept): (Vec<_>, Vec<_>) = mem::take(&mut self.fds).into_iter().partition(|(n, _)| n == name);
+        self.fds = kept;
+        taken.into_iter().map(|(_, fd)| fd).collect()
+    }
+
+    /// Takes the first socket named `name`, which must be a listening TCP socket (ListenStream= with an address
+    /// or port). Must be called within a tokio runtime.
+    pub fn take_tcp_listener(&mut self, name: &str) -> io::Result<tokio::net::TcpListener> {
+        let fd = self.take_checked(name, &[libc::AF_INET, libc::AF_INET6], libc::SOCK_STREAM, true)?;
+        let listener = std::net::TcpListener::from(fd);
+        listener.set_nonblocking(true)?;
+        tokio::net::TcpListener::from_std(listener)
+    }
+
+    /// Takes the first socket named `name`, which must be a listening Unix stream socket (ListenStream= with a
+    /// path). Must be called within a tokio runtime.
+    pub fn take_unix_listener(&mut self, name: &str) -> io::Result<tokio::net::UnixListener> {
+        let fd = self.take_checked(name, &[libc::AF_UNIX], libc::SOCK_STREAM, true)?;
+        let listener = std::os::unix::net::UnixListener::from(fd);
+        listener.set_nonblocking(true)?;
+        tokio::net::UnixListener::from_std(listener)
+    }
+
+    /// Takes the first socket named `name`, which must be a UDP socket (ListenDatagram= with an address or
+    /// port). Must be called within a tokio runtime.
+    pub fn take_udp_socket(&mut self, name: &str) -> io::Result<tokio::net::UdpSocket> {
+        let fd = self.take_checked(name, &[libc::AF_INET, libc::AF_INET6], libc::SOCK_DGRAM, false)?;
+        let socket = std::net::UdpSocket::from(fd);
+        socket.set_nonblocking(true)?;
+        tokio::net::UdpSocket::from_std(socket)
+    }
+
+    /// Takes the socket only if it has the expected family and type (and is listening, for stream sockets), so a
+    /// mismatch between the socket unit and the service leaves it in place.
+    fn take_checked(&mut self, name: &str, families: &[i32], kind: i32, listening: bool) -> io::Result<OwnedFd> {
+        let index = match self.fds.iter().position(|(n, _)| n == name) {
+            Some(index) => index,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no socket named {} was passed", name),
+                ))
+            }
+        };
+        let fd = &self.fds[index].1;
+        let family = socket_option(fd, libc::SO_DOMAIN)?;
+        let actual_kind = socket_option(fd, libc::SO_TYPE)?;
+        if !families.contains(&family) || actual_kind != kind {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("socket {} has family {} and type {}", name, family, actual_kind),
+            ));
+        }
+        if listening && socket_option(fd, libc::SO_ACCEPTCONN)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("socket {} isn't listening", name),
+            ));
+        }
+        Ok(self.fds.remove(index).1)
+    }
+}
+
+impl From<Vec<(String, OwnedFd)>> for ListenFds {
+    fn from(fds: Vec<(String, OwnedFd)>) -> Self {
+        Self { fds }
+    }
+}
+
+/// The descriptors and names passed to process `pid`, from the values of LISTEN_PID, LISTEN_FDS and
+/// LISTEN_FDNAMES.
+fn passed_fds(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    fd_names: Option<&str>,
+    pid: u32,
+) -> io::Result<Vec<(RawFd, String)>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    match listen_pid {
+        // Meant for another process, e.g. a parent that didn't unset them.
+        Some(listen_pid) if listen_pid.parse::<u32>().ok() == Some(pid) => {}
+        _ => return Ok(vec![]),
+    }
+    let count = match listen_fds {
+        Some(count) => count
+            .parse::<RawFd>()
+            .ok()
+            .filter(|count| *count >= 0)
+            .ok_or_else(|| invalid(format!("invalid {} {}", LISTEN_FDS, count)))?,
+        None => return Ok(vec![]),
+    };
+    if count == 0 {
+        return Ok(vec![]);
+    }
+    let names: Vec<String> = match fd_names {
+        Some(names) => names.split(':').map(String::from).collect(),
+        None => vec![UNKNOWN_NAME.to_string(); count as usize],
+    };
+    if names.len() != count as usize {
+        return Err(invalid(format!(
+            "{} names {} sockets, {} has {}",
+            LISTEN_FDNAMES,
+            names.len(),
+            LISTEN_FDS,
+            count
+        )));
+    }
+    Ok((LISTEN_FDS_START..LISTEN_FDS_START + count).zip(names).collect())
+}
+
+fn socket_option(fd: &OwnedFd, option: libc::c_int) -> io::Result<i32> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: value and len point to a c_int and its size, as these options expect.
+    let result = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+fn set_cloexec(fd: &OwnedFd) -> io::Result<()> {
+    // SAFETY: fcntl on a descriptor we own, with flag arguments only.
+    let result = unsafe {
+        let flags = libc::fcntl(fd.as_raw_fd(), libc::F_GETFD);
+        if flags < 0 {
+            flags
+        } else {
+            libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, flags | libc::FD_CLOEXEC)
+        }
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::io::OwnedFd;
+
+    use super::{passed_fds, ListenFds};
+
+    #[test]
+    fn parses_listen_environment() {
+        assert_eq!(
+            passed_fds(Some("42"), Some("2"), Some("http:metrics"), 42).unwrap(),
+            vec![(3, "http".to_string()), (4, "metrics".to_string())]
+        );
+        assert_eq!(
+            passed_fds(Some("42"), Some("1"), None, 42).unwrap(),
+            vec![(3, "unknown".to_string())]
+        );
+        assert!(passed_fds(Some("41"), Some("1"), None, 42).unwrap().is_empty());
+        assert!(passed_fds(None, Some("1"), None, 42).unwrap().is_empty());
+        assert!(passed_fds(Some("42"), Some("two"), None, 42).is_err());
+        assert!(passed_fds(Some("42"), Some("2"), Some("http"), 42).is_err());
+    }
+
+    #[tokio::test]
+    async fn converts_sockets_of_the_expected_type() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = tcp.local_addr().unwrap();
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(address).unwrap();
+        let mut fds = ListenFds::from(vec![
+            ("http".to_string(), OwnedFd::from(tcp)),
+            ("dns".to_string(), OwnedFd::from(udp)),
+            ("client".to_string(), OwnedFd::from(client)),
+        ]);
+
+        assert!(fds.take_udp_socket("http").is_err());
+        assert!(fds.take_unix_listener("http").is_err());
+        let listener = fds.take_tcp_listener("http").unwrap();
+        assert_eq!(listener.local_addr().unwrap(), address);
+        assert!(fds.take_tcp_listener("http").is_err());
+
+        assert!(fds.take_tcp_listener("dns").is_err());
+        fds.take_udp_socket("dns").unwrap();
+        // Connected, not listening.
+        assert!(fds.take_tcp_listener("client").is_err());
+        assert_eq!(fds.names().collect::<Vec<_>>(), vec!["client"]);
+    }
+
+    #[test]
+    fn takes_all_fds_of_a_name() {
+        let file = |_| OwnedFd::from(std::fs::File::open("/dev/null").unwrap());
+        let mut fds = ListenFds::from(vec![
+            ("conn".to_string(), file(0)),
+            ("http".to_string(), file(1)),
+            ("conn".to_string(), file(2)),
+        ]);
+        assert_eq!(fds.take_all("conn").len(), 2);
+        assert!(fds.take_all("conn").is_empty());
+        assert_eq!(fds.names().collect::<Vec<_>>(), vec!["http"]);
+    }
+}
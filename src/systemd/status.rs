@@ -0,0 +1,428 @@
+use chrono::{DateTime, Local};
+use dbus::arg;
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::debug;
+
+use super::{
+    cgroup_interface, property_str, property_strings, property_timestamp, property_u64, Boot, CGroupTree, LogEntry,
+    LogFilter, SystemdManager, TimeSpan, UnitProcess, SYSTEMD_UNIT,
+};
+
+/// Everything `systemctl status` shows about a unit; `Display` renders it the same way.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitReport {
+    pub name: String,
+    pub description: String,
+    pub load_state: String,
+    pub fragment_path: Option<String>,
+    pub drop_in_paths: Vec<String>,
+    pub unit_file_state: Option<String>,
+    pub unit_file_preset: Option<String>,
+    pub active_state: String,
+    pub sub_state: String,
+    /// The unit's Result property (exit-code, signal, timeout, ...), for services and the like.
+    pub result: Option<String>,
+    /// When the unit entered its current active state.
+    pub since: Option<SystemTime>,
+    pub invocation_id: Option<String>,
+    pub main_pid: Option<u32>,
+    /// Name of the main process, as in /proc/<pid>/comm.
+    pub main_process: Option<String>,
+    pub tasks: Option<u64>,
+    pub tasks_max: Option<u64>,
+    pub memory: Option<u64>,
+    pub memory_peak: Option<u64>,
+    pub cpu: Option<Duration>,
+    pub control_group: Option<String>,
+    /// Every process in the unit's cgroup and its sub-cgroups.
+    pub processes: Vec<UnitProcess>,
+    pub logs: Vec<LogEntry>,
+}
+
+impl UnitReport {
+    fn from_properties(name: &str, unit: &arg::PropMap, typed: &arg::PropMap) -> Self {
+        let text = |properties: &arg::PropMap, property: &str| {
+            property_str(properties, property)
+                .filter(|value| !value.is_empty())
+                .map(String::from)
+        };
+        let active_state = property_str(unit, "ActiveState").unwrap_or_default().to_string();
+        let since = match active_state.as_str() {
+            "active" | "reloading" => "ActiveEnterTimestamp",
+            "activating" => "InactiveExitTimestamp",
+            "deactivating" => "ActiveExitTimestamp",
+            _ => "InactiveEnterTimestamp",
+        };
+        let invocation_id = unit
+            .get("InvocationID")
+            .and_then(|v| v.0.as_iter())
+            .map(|bytes| {
+                bytes
+                    .filter_map(|b| b.as_u64())
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>()
+            })
+            .filter(|id| !id.is_empty());
+
+        Self {
+            name: property_str(unit, "Id").unwrap_or(name).to_string(),
+            description: property_str(unit, "Description").unwrap_or_default().to_string(),
+            load_state: property_str(unit, "LoadState").unwrap_or_default().to_string(),
+            fragment_path: text(unit, "FragmentPath"),
+            drop_in_paths: property_strings(unit, "DropInPaths"),
+            unit_file_state: text(unit, "UnitFileState"),
+            unit_file_preset: text(unit, "UnitFilePreset"),
+            sub_state: property_str(unit, "SubState").unwrap_or_default().to_string(),
+            active_state,
+            result: text(typed, "Result"),
+            since: property_timestamp(unit, since).map(|usec| UNIX_EPOCH + Duration::from_micros(usec)),
+            invocation_id,
+            main_pid: property_u64(typed, "MainPID")
+                .filter(|pid| *pid != 0)
+                .map(|pid| pid as u32),
+            main_process: None,
+            tasks: property_u64(typed, "TasksCurrent"),
+            tasks_max: property_u64(typed, "TasksMax"),
+            memory: property_u64(typed, "MemoryCurrent"),
+            memory_peak: property_u64(typed, "MemoryPeak"),
+            cpu: property_u64(typed, "CPUUsageNSec").map(Duration::from_nanos),
+            control_group: text(typed, "ControlGroup"),
+            processes: vec![],
+            logs: vec![],
+        }
+    }
+
+    fn icon(&self) -> &'static str {
+        match self.active_state.as_str() {
+            "failed" => "×",
+            "inactive" | "maintenance" => "○",
+            _ => "●",
+        }
+    }
+}
+
+impl fmt::Display for UnitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const INDENT: &str = "             ";
+
+        write!(f, "{} {}", self.icon(), self.name)?;
+        if !self.description.is_empty() {
+            write!(f, " - {}", self.description)?;
+        }
+        writeln!(f)?;
+
+        let mut loaded = vec![];
+        if let Some(fragment_path) = &self.fragment_path {
+            loaded.push(fragment_path.clone());
+        }
+        if let Some(state) = &self.unit_file_state {
+            loaded.push(state.clone());
+        }
+        if let Some(preset) = &self.unit_file_preset {
+            loaded.push(format!("preset: {}", preset));
+        }
+        if loaded.is_empty() {
+            writeln!(f, "{:>11}: {}", "Loaded", self.load_state)?;
+        } else {
+            writeln!(f, "{:>11}: {} ({})", "Loaded", self.load_state, loaded.join("; "))?;
+        }
+
+        // Drop-ins are grouped by directory, files of one directory on one line.
+        let mut drop_in_dirs: Vec<(&str, Vec<&str>)> = vec![];
+        for path in self.drop_in_paths.iter() {
+            let (dir, file) = path.rsplit_once('/').unwrap_or(("", path));
+            match drop_in_dirs.iter_mut().find(|(d, _)| *d == dir) {
+                Some((_, files)) => files.push(file),
+                None => drop_in_dirs.push((dir, vec![file])),
+            }
+        }
+        for (i, (dir, files)) in drop_in_dirs.iter().enumerate() {
+            if i == 0 {
+                writeln!(f, "{:>11}: {}", "Drop-In", dir)?;
+            } else {
+                writeln!(f, "{}{}", INDENT, dir)?;
+            }
+            writeln!(f, "{}└─{}", INDENT, files.join(", "))?;
+        }
+
+        write!(f, "{:>11}: {}", "Active", self.active_state)?;
+        if self.sub_state != self.active_state {
+            write!(f, " ({})", self.sub_state)?;
+        }
+        match &self.result {
+            Some(result) if result != "success" => write!(f, " (Result: {})", result)?,
+            _ => {}
+        }
+        if let Some(since) = self.since {
+            let ago = SystemTime::now().duration_since(since).unwrap_or_default();
+            write!(
+                f,
+                " since {}; {}",
+                DateTime::<Local>::from(since).format("%a %Y-%m-%d %H:%M:%S %Z"),
+                format_relative(ago)
+            )?;
+        }
+        writeln!(f)?;
+        if let Some(invocation_id) = &self.invocation_id {
+            writeln!(f, "{:>11}: {}", "Invocation", invocation_id)?;
+        }
+
+        if let Some(pid) = self.main_pid {
+            match &self.main_process {
+                Some(process) => writeln!(f, "{:>11}: {} ({})", "Main PID", pid, process)?,
+                None => writeln!(f, "{:>11}: {}", "Main PID", pid)?,
+            }
+        }
+        if let Some(tasks) = self.tasks {
+            match self.tasks_max {
+                Some(limit) => writeln!(f, "{:>11}: {} (limit: {})", "Tasks", tasks, limit)?,
+                None => writeln!(f, "{:>11}: {}", "Tasks", tasks)?,
+            }
+        }
+        if let Some(memory) = self.memory {
+            match self.memory_peak {
+                Some(peak) => writeln!(
+                    f,
+                    "{:>11}: {} (peak: {})",
+                    "Memory",
+                    format_bytes(memory),
+                    format_bytes(peak)
+                )?,
+                None => writeln!(f, "{:>11}: {}", "Memory", format_bytes(memory))?,
+            }
+        }
+        if let Some(cpu) = self.cpu {
+            let cpu = TimeSpan::from(cpu).format(Duration::from_millis(1));
+            writeln!(f, "{:>11}: {}", "CPU", cpu)?;
+        }
+        if let Some(control_group) = &self.control_group {
+            let tree = CGroupTree::build(control_group, &self.processes).to_string();
+            for (i, line) in tree.lines().enumerate() {
+                if i == 0 {
+                    writeln!(f, "{:>11}: {}", "CGroup", line)?;
+                } else {
+                    writeln!(f, "{}{}", INDENT, line)?;
+                }
+            }
+        }
+
+        if !self.logs.is_empty() {
+            writeln!(f)?;
+            for entry in self.logs.iter() {
+                writeln!(f, "{}", entry)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SystemdManager {
+    /// Gathers what `systemctl status` shows for `name`, including the last `lines` journal lines of this boot.
+    ///
+    /// Only the Unit properties are required; processes and logs are left empty if they can't be read, e.g.
+    /// because the unit isn't running or the journal isn't readable by this user.
+    pub async fn unit_status(&self, name: &str, lines: usize) -> Result<UnitReport, dbus::MethodErr> {
+        let unit = self.get_unit_properties(name, SYSTEMD_UNIT.interface).await?;
+        let typed = match cgroup_interface(name) {
+            Some(interface) => self.get_unit_properties(name, interface).await?,
+            None => arg::PropMap::new(),
+        };
+        let mut report = UnitReport::from_properties(name, &unit, &typed);
+
+        if let Some(pid) = report.main_pid {
+            if let Ok(comm) = tokio::fs::read_to_string(format!("/proc/{}/comm", pid)).await {
+                report.main_process = Some(comm.trim_end().to_string());
+            }
+        }
+        if report.control_group.is_some() {
+            match self.unit_processes(name).await {
+                Ok(processes) => report.processes = processes,
+                Err(e) => debug!("{:?}", e),
+            }
+        }
+        if lines > 0 {
+            let filter = LogFilter {
+                boot: Some(Boot::Current),
+                limit: Some(lines),
+                ..LogFilter::default()
+            };
+            match self.logs(name, filter).await {
+                Ok(logs) => report.logs = logs,
+                Err(e) => debug!("{:?}", e),
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// Byte counts the way systemd prints them: `512B`, `1.0K`, `5.2M`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[(&str, u64)] = &[
+        ("E", 1 << 60),
+        ("P", 1 << 50),
+        ("T", 1 << 40),
+        ("G", 1 << 30),
+        ("M", 1 << 20),
+        ("K", 1 << 10),
+    ];
+    for (suffix, factor) in UNITS.iter() {
+        if bytes >= *factor {
+            return format!("{}.{}{}", bytes / factor, bytes % factor * 10 / factor, suffix);
+        }
+    }
+    format!("{}B", bytes)
+}
+
+/// How long ago something happened, the way systemd prints it after a timestamp: `2h 5min ago`.
+pub fn format_relative(ago: Duration) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+    const WEEK: u64 = 7 * DAY;
+    const MONTH: u64 = 2_629_800;
+    const YEAR: u64 = 31_557_600;
+
+    let s = ago.as_secs();
+    if s >= YEAR {
+        format!("{} years {} months ago", s / YEAR, s % YEAR / MONTH)
+    } else if s >= MONTH {
+        format!("{} months {} days ago", s / MONTH, s % MONTH / DAY)
+    } else if s >= WEEK {
+        format!("{} weeks {} days ago", s / WEEK, s % WEEK / DAY)
+    } else if s >= 2 * DAY {
+        format!("{} days ago", s / DAY)
+    } else if s >= 25 * HOUR {
+        format!("1 day {}h ago", (s - DAY) / HOUR)
+    } else if s >= 6 * HOUR {
+        format!("{}h ago", s / HOUR)
+    } else if s >= HOUR {
+        format!("{}h {}min ago", s / HOUR, s % HOUR / MINUTE)
+    } else if s >= 5 * MINUTE {
+        format!("{}min ago", s / MINUTE)
+    } else if s >= MINUTE {
+        format!("{}min {}s ago", s / MINUTE, s % MINUTE)
+    } else if s > 0 {
+        format!("{}s ago", s)
+    } else if ago.as_millis() > 0 {
+        format!("{}ms ago", ago.as_millis())
+    } else if ago.as_micros() > 0 {
+        format!("{}us ago", ago.as_micros())
+    } else {
+        "now".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{format_bytes, format_relative, UnitProcess, UnitReport};
+
+    #[test]
+    fn renders_like_systemctl_status() {
+        let report = UnitReport {
+            name: "nginx.service".to_string(),
+            description: "A high performance web server".to_string(),
+            load_state: "loaded".to_string(),
+            fragment_path: Some("/lib/systemd/system/nginx.service".to_string()),
+            drop_in_paths: vec![
+                "/etc/systemd/system/nginx.service.d/10-limits.conf".to_string(),
+                "/etc/systemd/system/nginx.service.d/override.conf".to_string(),
+                "/run/systemd/system/nginx.service.d/50-runtime.conf".to_string(),
+            ],
+            unit_file_state: Some("enabled".to_string()),
+            unit_file_preset: Some("enabled".to_string()),
+            active_state: "active".to_string(),
+            sub_state: "running".to_string(),
+            result: Some("success".to_string()),
+            invocation_id: Some("5f2ae8b0c4d34e59a1c07e6b2d9f3a11".to_string()),
+            main_pid: Some(1234),
+            main_process: Some("nginx".to_string()),
+            tasks: Some(3),
+            tasks_max: Some(4915),
+            memory: Some(5_452_596),
+            cpu: Some(Duration::from_micros(1_234_567)),
+            control_group: Some("/system.slice/nginx.service".to_string()),
+            processes: vec![
+                UnitProcess {
+                    cgroup_path: "/system.slice/nginx.service".to_string(),
+                    pid: 1234,
+                    cmdline: "nginx: master process /usr/sbin/nginx".to_string(),
+                },
+                UnitProcess {
+                    cgroup_path: "/system.slice/nginx.service".to_string(),
+                    pid: 1235,
+                    cmdline: "nginx: worker process".to_string(),
+                },
+                UnitProcess {
+                    cgroup_path: "/system.slice/nginx.service/cache".to_string(),
+                    pid: 1240,
+                    cmdline: "nginx: cache manager process".to_string(),
+                },
+            ],
+            ..UnitReport::default()
+        };
+
+        let expected = "\
+● nginx.service - A high performance web server
+     Loaded: loaded (/lib/systemd/system/nginx.service; enabled; preset: enabled)
+    Drop-In: /etc/systemd/system/nginx.service.d
+             └─10-limits.conf, override.conf
+             /run/systemd/system/nginx.service.d
+             └─50-runtime.conf
+     Active: active (running)
+ Invocation: 5f2ae8b0c4d34e59a1c07e6b2d9f3a11
+   Main PID: 1234 (nginx)
+      Tasks: 3 (limit: 4915)
+     Memory: 5.2M
+        CPU: 1.234s
+     CGroup: /system.slice/nginx.service
+             ├─1234 nginx: master process /usr/sbin/nginx
+             ├─1235 nginx: worker process
+             └─cache
+               └─1240 nginx: cache manager process
+";
+        assert_eq!(report.to_string(), expected);
+    }
+
+    #[test]
+    fn failed_units_show_their_result() {
+        let report = UnitReport {
+            name: "batch.service".to_string(),
+            load_state: "loaded".to_string(),
+            active_state: "failed".to_string(),
+            sub_state: "failed".to_string(),
+            result: Some("exit-code".to_string()),
+            ..UnitReport::default()
+        };
+        assert_eq!(
+            report.to_string(),
+            "× batch.service\n     Loaded: loaded\n     Active: failed (Result: exit-code)\n"
+        );
+
+        let timed_out = UnitReport {
+            active_state: "inactive".to_string(),
+            sub_state: "dead".to_string(),
+            result: Some("timeout".to_string()),
+            ..report
+        };
+        assert!(timed_out
+            .to_string()
+            .contains("     Active: inactive (dead) (Result: timeout)\n"));
+    }
+
+    #[test]
+    fn formats_sizes_and_ages_like_systemd() {
+        assert_eq!(format_bytes(512), "512B");
+        assert_eq!(format_bytes(1024), "1.0K");
+        assert_eq!(format_bytes(5_452_596), "5.2M");
+        assert_eq!(format_relative(Duration::from_secs(90)), "1min 30s ago");
+        assert_eq!(format_relative(Duration::from_secs(2 * 3600 + 5 * 60)), "2h 5min ago");
+        assert_eq!(format_relative(Duration::from_secs(3 * 86400)), "3 days ago");
+        assert_eq!(format_relative(Duration::from_millis(250)), "250ms ago");
+    }
+}
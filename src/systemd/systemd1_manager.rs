@@ -1,13 +1,14 @@
 use dbus::arg;
 
-use super::{Mode, UnitStatusDto};
+use super::{Mode, UnitProcessDto, UnitStatusDto};
 
 #[async_trait::async_trait]
 pub trait Systemd1Manager {
     async fn get_unit(&self, name: &str) -> Result<dbus::Path<'static>, dbus_tree::MethodErr>;
     async fn get_unit_by_pid(&self, pid: u32) -> Result<dbus::Path<'static>, dbus_tree::MethodErr>;
     async fn load_unit(&self, name: &str) -> Result<dbus::Path<'static>, dbus_tree::MethodErr>;
-    // mode strings: replace, fail, isolate, ignore-dependencies, ignore-requirement
+    // mode strings: replace, replace-irreversibly, fail, isolate, flush, ignore-dependencies, ignore-requirements,
+    // triggering, restart-dependencies; see Mode
     async fn start_unit(&self, name: &str, mode: &Mode) -> Result<dbus::Path<'static>, dbus_tree::MethodErr>;
     async fn start_unit_replace(
         &self,
@@ -31,6 +32,15 @@ pub trait Systemd1Manager {
     ) -> Result<dbus::Path<'static>, dbus_tree::MethodErr>;
     async fn kill_unit(&self, name: &str, who: &str, signal: i32) -> Result<(), dbus_tree::MethodErr>;
     async fn reset_failed_unit(&self, name: &str) -> Result<(), dbus_tree::MethodErr>;
+    // (cgroup path, pid, command line) for every process in the unit's cgroup and its sub-cgroups; see UnitProcess
+    async fn get_unit_processes(&self, name: &str) -> Result<Vec<UnitProcessDto>, dbus_tree::MethodErr>;
+    // subcgroup is relative to the unit's cgroup, "" for the unit's own; see DelegatedCGroup
+    async fn attach_processes_to_unit(
+        &self,
+        name: &str,
+        subcgroup: &str,
+        pids: Vec<u32>,
+    ) -> Result<(), dbus_tree::MethodErr>;
     async fn set_unit_properties(
         &self,
         name: &str,
@@ -50,6 +60,14 @@ pub trait Systemd1Manager {
     async fn reset_failed(&self) -> Result<(), dbus_tree::MethodErr>;
     async fn list_units(&self) -> Result<Vec<UnitStatusDto>, dbus_tree::MethodErr>;
     async fn list_units_filtered(&self, names: Vec<&str>) -> Result<Vec<UnitStatusDto>, dbus_tree::MethodErr>;
+    // states match the load, active or sub state; patterns are fnmatch globs on the unit name; see UnitQuery
+    async fn list_units_by_patterns(
+        &self,
+        states: Vec<&str>,
+        patterns: Vec<&str>,
+    ) -> Result<Vec<UnitStatusDto>, dbus_tree::MethodErr>;
+    // loads the named units from disk if necessary, so inactive units are listed too
+    async fn list_units_by_names(&self, names: Vec<&str>) -> Result<Vec<UnitStatusDto>, dbus_tree::MethodErr>;
     async fn list_jobs(
         &self,
     ) -> Result<Vec<(u32, String, String, String, dbus::Path<'static>, dbus::Path<'static>)>, dbus_tree::MethodErr>;
@@ -175,12 +193,19 @@ pub trait Systemd1Manager {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Systemd1ManagerStartupFinished {
+    #[cfg_attr(feature = "serde", serde(rename = "firmware_usec"))]
     pub arg0: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "loader_usec"))]
     pub arg1: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "kernel_usec"))]
     pub arg2: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "initrd_usec"))]
     pub arg3: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "userspace_usec"))]
     pub arg4: u64,
+    #[cfg_attr(feature = "serde", serde(rename = "total_usec"))]
     pub arg5: u64,
 }
 
@@ -214,7 +239,9 @@ impl dbus::message::SignalArgs for Systemd1ManagerStartupFinished {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Systemd1ManagerReloading {
+    #[cfg_attr(feature = "serde", serde(rename = "active"))]
     pub arg0: bool,
 }
 
@@ -1,13 +1,13 @@
 use dbus::Path as DbusPath;
 use strum::{AsRefStr, AsStaticStr, IntoStaticStr};
 
-use crate::dbus::{DBusConnectionPool, DbusConnectionManager};
+use crate::dbus::{Bus, DBusConnection, DBusConnectionPool, DbusConnectionManager};
 
-use super::{JobDto, Mode, Systemd1Manager, UnitStatusDto};
+use super::{unit_object_path, JobDto, JobType, Mode, Systemd1Manager, UnitProcessDto, UnitStatusDto};
 
 #[derive(Clone)]
 pub struct SystemdManager {
-    connection_pool: DBusConnectionPool,
+    pub(crate) connection_pool: DBusConnectionPool,
 }
 
 impl std::fmt::Debug for SystemdManager {
@@ -19,7 +19,77 @@ impl std::fmt::Debug for SystemdManager {
 impl SystemdManager {
     pub fn default() -> Self {
         Self {
-            connection_pool: DBusConnectionPool::new(DbusConnectionManager, 5),
+            connection_pool: DBusConnectionPool::new(DbusConnectionManager::default(), 5),
+        }
+    }
+
+    /// A manager for the calling user's systemd instance, on the session bus, like `systemctl --user`.
+    pub fn user() -> Self {
+        Self {
+            connection_pool: DBusConnectionPool::new(DbusConnectionManager::new(Bus::Session), 5),
+        }
+    }
+
+    /// Checks out a pooled connection for work the proxy calls can't do, like adding signal matches.
+    pub(crate) async fn connection(&self) -> Result<DBusConnection, dbus::MethodErr> {
+        match self.connection_pool.get().await {
+            Ok(connection) => Ok(connection.clone()),
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
+    }
+
+    /// All properties of `interface` (e.g. org.freedesktop.systemd1.Service) on the named unit's object.
+    pub async fn get_unit_properties(
+        &self,
+        name: &str,
+        interface: &str,
+    ) -> Result<dbus::arg::PropMap, dbus::MethodErr> {
+        match DbusConnectionManager::make_dbus_proxy(
+            SYSTEMD.service.into(),
+            unit_object_path(name).to_string(),
+            &self.connection_pool,
+        )
+        .await
+        {
+            Ok(proxy) => match proxy
+                .method_call("org.freedesktop.DBus.Properties", "GetAll", (interface,))
+                .await
+            {
+                Ok((properties,)) => {
+                    let properties: dbus::arg::PropMap = properties;
+                    Ok(properties)
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
+    }
+
+    /// All properties of the Manager itself, like NFailedUnits, NJobs and the boot timestamps.
+    pub async fn get_manager_properties(&self) -> Result<dbus::arg::PropMap, dbus::MethodErr> {
+        match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
+            .await
+        {
+            Ok(proxy) => match proxy
+                .method_call("org.freedesktop.DBus.Properties", "GetAll", (SYSTEMD.interface,))
+                .await
+            {
+                Ok((properties,)) => {
+                    let properties: dbus::arg::PropMap = properties;
+                    Ok(properties)
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
         }
     }
 }
@@ -63,29 +133,36 @@ impl Systemd1Manager for SystemdManager {
     }
 
     async fn load_unit(&self, name: &str) -> Result<DbusPath<'static>, dbus::MethodErr> {
-        todo!()
+        match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
+            .await
+        {
+            Ok(proxy) => match proxy.method_call(SYSTEMD.interface, "LoadUnit", (name,)).await {
+                Ok(result) => {
+                    let result: (DbusPath<'static>,) = result;
+                    Ok(result.0)
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
     }
 
     async fn start_unit(&self, name: &str, mode: &Mode) -> Result<DbusPath<'static>, dbus::MethodErr> {
+        mode.validate(JobType::Start, name)?;
+
         match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
             .await
         {
-            Ok(proxy) => {
-                match proxy
-                    .method_call(
-                        SYSTEMD.interface,
-                        "StartUnit",
-                        (name, <&'static str>::from(mode).to_lowercase().as_str()),
-                    )
-                    .await
-                {
-                    Ok((path,)) => {
-                        let path: DbusPath<'_> = path;
-                        Ok(path)
-                    }
-                    Err(e) => Err(dbus::MethodErr::from(e)),
+            Ok(proxy) => match proxy.method_call(SYSTEMD.interface, "StartUnit", (name, mode)).await {
+                Ok((path,)) => {
+                    let path: DbusPath<'_> = path;
+                    Ok(path)
                 }
-            }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
             Err(e) => {
                 let message = format!("{:?}", e);
                 Err(dbus::MethodErr::failed(&message))
@@ -99,15 +176,13 @@ impl Systemd1Manager for SystemdManager {
         new_unit: &str,
         mode: &Mode,
     ) -> Result<DbusPath<'static>, dbus::MethodErr> {
+        mode.validate(JobType::Start, new_unit)?;
+
         match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
             .await
         {
             Ok(proxy) => match proxy
-                .method_call(
-                    SYSTEMD.interface,
-                    "StartUnitReplace",
-                    (old_unit, new_unit, <&'static str>::from(mode).to_lowercase().as_str()),
-                )
+                .method_call(SYSTEMD.interface, "StartUnitReplace", (old_unit, new_unit, mode))
                 .await
             {
                 Ok((path,)) => {
@@ -124,17 +199,12 @@ impl Systemd1Manager for SystemdManager {
     }
 
     async fn stop_unit(&self, name: &str, mode: &Mode) -> Result<DbusPath<'static>, dbus::MethodErr> {
+        mode.validate(JobType::Stop, name)?;
+
         match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
             .await
         {
-            Ok(proxy) => match proxy
-                .method_call(
-                    SYSTEMD.interface,
-                    "StopUnit",
-                    (name, <&'static str>::from(mode).to_lowercase().as_str()),
-                )
-                .await
-            {
+            Ok(proxy) => match proxy.method_call(SYSTEMD.interface, "StopUnit", (name, mode)).await {
                 Ok((path,)) => {
                     let path: DbusPath<'_> = path;
                     Ok(path)
@@ -149,17 +219,12 @@ impl Systemd1Manager for SystemdManager {
     }
 
     async fn reload_unit(&self, name: &str, mode: &Mode) -> Result<DbusPath<'static>, dbus::MethodErr> {
+        mode.validate(JobType::Reload, name)?;
+
         match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
             .await
         {
-            Ok(proxy) => match proxy
-                .method_call(
-                    SYSTEMD.interface,
-                    "ReloadUnit",
-                    (name, <&'static str>::from(mode).to_lowercase().as_str()),
-                )
-                .await
-            {
+            Ok(proxy) => match proxy.method_call(SYSTEMD.interface, "ReloadUnit", (name, mode)).await {
                 Ok((path,)) => {
                     let path: DbusPath<'_> = path;
                     Ok(path)
@@ -174,17 +239,12 @@ impl Systemd1Manager for SystemdManager {
     }
 
     async fn restart_unit(&self, name: &str, mode: &Mode) -> Result<DbusPath<'static>, dbus::MethodErr> {
+        mode.validate(JobType::Restart, name)?;
+
         match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
             .await
         {
-            Ok(proxy) => match proxy
-                .method_call(
-                    SYSTEMD.interface,
-                    "RestartUnit",
-                    (name, <&'static str>::from(mode).to_lowercase().as_str()),
-                )
-                .await
-            {
+            Ok(proxy) => match proxy.method_call(SYSTEMD.interface, "RestartUnit", (name, mode)).await {
                 Ok((path,)) => {
                     let path: DbusPath<'_> = path;
                     Ok(path)
@@ -199,15 +259,13 @@ impl Systemd1Manager for SystemdManager {
     }
 
     async fn try_restart_unit(&self, name: &str, mode: &Mode) -> Result<DbusPath<'static>, dbus::MethodErr> {
+        mode.validate(JobType::Restart, name)?;
+
         match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
             .await
         {
             Ok(proxy) => match proxy
-                .method_call(
-                    SYSTEMD.interface,
-                    "TryRestartUnit",
-                    (name, <&'static str>::from(mode).to_lowercase().as_str()),
-                )
+                .method_call(SYSTEMD.interface, "TryRestartUnit", (name, mode))
                 .await
             {
                 Ok((path,)) => {
@@ -224,15 +282,13 @@ impl Systemd1Manager for SystemdManager {
     }
 
     async fn reload_or_restart_unit(&self, name: &str, mode: &Mode) -> Result<DbusPath<'static>, dbus::MethodErr> {
+        mode.validate(JobType::Restart, name)?;
+
         match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
             .await
         {
             Ok(proxy) => match proxy
-                .method_call(
-                    SYSTEMD.interface,
-                    "ReloadOrRestartUnit",
-                    (name, <&'static str>::from(mode).to_lowercase().as_str()),
-                )
+                .method_call(SYSTEMD.interface, "ReloadOrRestartUnit", (name, mode))
                 .await
             {
                 Ok((path,)) => {
@@ -249,15 +305,13 @@ impl Systemd1Manager for SystemdManager {
     }
 
     async fn reload_or_try_restart_unit(&self, name: &str, mode: &Mode) -> Result<DbusPath<'static>, dbus::MethodErr> {
+        mode.validate(JobType::Restart, name)?;
+
         match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
             .await
         {
             Ok(proxy) => match proxy
-                .method_call(
-                    SYSTEMD.interface,
-                    "ReloadOrTryRestartUnit",
-                    (name, <&'static str>::from(mode).to_lowercase().as_str()),
-                )
+                .method_call(SYSTEMD.interface, "ReloadOrTryRestartUnit", (name, mode))
                 .await
             {
                 Ok((path,)) => {
@@ -312,6 +366,50 @@ impl Systemd1Manager for SystemdManager {
         }
     }
 
+    async fn get_unit_processes(&self, name: &str) -> Result<Vec<UnitProcessDto>, dbus::MethodErr> {
+        match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
+            .await
+        {
+            Ok(proxy) => match proxy.method_call(SYSTEMD.interface, "GetUnitProcesses", (name,)).await {
+                Ok((processes,)) => {
+                    let processes: Vec<UnitProcessDto> = processes;
+                    Ok(processes)
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
+    }
+
+    async fn attach_processes_to_unit(
+        &self,
+        name: &str,
+        subcgroup: &str,
+        pids: Vec<u32>,
+    ) -> Result<(), dbus::MethodErr> {
+        match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
+            .await
+        {
+            Ok(proxy) => match proxy
+                .method_call(SYSTEMD.interface, "AttachProcessesToUnit", (name, subcgroup, pids))
+                .await
+            {
+                Ok(attached) => {
+                    let attached: () = attached;
+                    Ok(())
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
+    }
+
     async fn set_unit_properties(
         &self,
         name: &str,
@@ -345,20 +443,13 @@ impl Systemd1Manager for SystemdManager {
         properties: Vec<(&str, dbus::arg::Variant<Box<dyn dbus::arg::RefArg>>)>,
         aux: Vec<(&str, Vec<(&str, dbus::arg::Variant<Box<dyn dbus::arg::RefArg>>)>)>,
     ) -> Result<DbusPath<'static>, dbus::MethodErr> {
+        mode.validate(JobType::Start, name)?;
+
         match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
             .await
         {
             Ok(proxy) => match proxy
-                .method_call(
-                    SYSTEMD.interface,
-                    "StartTransientUnit",
-                    (
-                        name,
-                        <&'static str>::from(mode).to_lowercase().as_str(),
-                        properties,
-                        aux,
-                    ),
-                )
+                .method_call(SYSTEMD.interface, "StartTransientUnit", (name, mode, properties, aux))
                 .await
             {
                 Ok((path,)) => {
@@ -485,6 +576,49 @@ impl Systemd1Manager for SystemdManager {
         }
     }
 
+    async fn list_units_by_patterns(
+        &self,
+        states: Vec<&str>,
+        patterns: Vec<&str>,
+    ) -> Result<Vec<UnitStatusDto>, dbus::MethodErr> {
+        match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
+            .await
+        {
+            Ok(proxy) => match proxy
+                .method_call(SYSTEMD.interface, "ListUnitsByPatterns", (states, patterns))
+                .await
+            {
+                Ok((units,)) => {
+                    let units: Vec<UnitStatusDto> = units;
+                    Ok(units)
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
+    }
+
+    async fn list_units_by_names(&self, names: Vec<&str>) -> Result<Vec<UnitStatusDto>, dbus::MethodErr> {
+        match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
+            .await
+        {
+            Ok(proxy) => match proxy.method_call(SYSTEMD.interface, "ListUnitsByNames", (names,)).await {
+                Ok((units,)) => {
+                    let units: Vec<UnitStatusDto> = units;
+                    Ok(units)
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
+    }
+
     async fn list_jobs(&self) -> Result<Vec<JobDto>, dbus::MethodErr> {
         match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
             .await
@@ -743,11 +877,42 @@ impl Systemd1Manager for SystemdManager {
     }
 
     async fn list_unit_files(&self) -> Result<Vec<(String, String)>, dbus::MethodErr> {
-        todo!()
+        match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
+            .await
+        {
+            Ok(proxy) => match proxy.method_call(SYSTEMD.interface, "ListUnitFiles", ()).await {
+                Ok(result) => {
+                    let result: (Vec<(String, String)>,) = result;
+                    Ok(result.0)
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
     }
 
     async fn get_unit_file_state(&self, file_path: &str) -> Result<String, dbus::MethodErr> {
-        todo!()
+        match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
+            .await
+        {
+            Ok(proxy) => match proxy
+                .method_call(SYSTEMD.interface, "GetUnitFileState", (file_path,))
+                .await
+            {
+                Ok(result) => {
+                    let result: (String,) = result;
+                    Ok(result.0)
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
     }
 
     async fn enable_unit_files(
@@ -756,7 +921,24 @@ impl Systemd1Manager for SystemdManager {
         runtime: bool,
         force: bool,
     ) -> Result<(bool, Vec<(String, String, String)>), dbus::MethodErr> {
-        todo!()
+        match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
+            .await
+        {
+            Ok(proxy) => match proxy
+                .method_call(SYSTEMD.interface, "EnableUnitFiles", (files, runtime, force))
+                .await
+            {
+                Ok(result) => {
+                    let result: (bool, Vec<(String, String, String)>) = result;
+                    Ok(result)
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
     }
 
     async fn disable_unit_files(
@@ -764,7 +946,24 @@ impl Systemd1Manager for SystemdManager {
         files: Vec<&str>,
         runtime: bool,
     ) -> Result<Vec<(String, String, String)>, dbus::MethodErr> {
-        todo!()
+        match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
+            .await
+        {
+            Ok(proxy) => match proxy
+                .method_call(SYSTEMD.interface, "DisableUnitFiles", (files, runtime))
+                .await
+            {
+                Ok(result) => {
+                    let result: (Vec<(String, String, String)>,) = result;
+                    Ok(result.0)
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
     }
 
     async fn re_enable_unit_files(
@@ -800,7 +999,24 @@ impl Systemd1Manager for SystemdManager {
         runtime: bool,
         force: bool,
     ) -> Result<Vec<(String, String, String)>, dbus::MethodErr> {
-        todo!()
+        match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
+            .await
+        {
+            Ok(proxy) => match proxy
+                .method_call(SYSTEMD.interface, "MaskUnitFiles", (files, runtime, force))
+                .await
+            {
+                Ok(result) => {
+                    let result: (Vec<(String, String, String)>,) = result;
+                    Ok(result.0)
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
     }
 
     async fn unmask_unit_files(
@@ -808,7 +1024,24 @@ impl Systemd1Manager for SystemdManager {
         files: Vec<&str>,
         runtime: bool,
     ) -> Result<Vec<(String, String, String)>, dbus::MethodErr> {
-        todo!()
+        match DbusConnectionManager::make_dbus_proxy(SYSTEMD.service.into(), SYSTEMD.path.into(), &self.connection_pool)
+            .await
+        {
+            Ok(proxy) => match proxy
+                .method_call(SYSTEMD.interface, "UnmaskUnitFiles", (files, runtime))
+                .await
+            {
+                Ok(result) => {
+                    let result: (Vec<(String, String, String)>,) = result;
+                    Ok(result.0)
+                }
+                Err(e) => Err(dbus::MethodErr::from(e)),
+            },
+            Err(e) => {
+                let message = format!("{:?}", e);
+                Err(dbus::MethodErr::failed(&message))
+            }
+        }
     }
 
     async fn set_default_target(
@@ -1074,26 +1307,32 @@ impl Systemd1Manager for SystemdManager {
 }
 
 #[derive(Debug)]
-struct Object {
-    service: &'static str,
-    path: &'static str,
-    interface: &'static str,
+pub(crate) struct Object {
+    pub(crate) service: &'static str,
+    pub(crate) path: &'static str,
+    pub(crate) interface: &'static str,
 }
 
-static SYSTEMD: &Object = &Object {
+pub(crate) static SYSTEMD: &Object = &Object {
     service: "org.freedesktop.systemd1",
     path: "/org/freedesktop/systemd1",
     interface: "org.freedesktop.systemd1.Manager",
 };
 
-static SYSTEMD_SERVICE: &Object = &Object {
+pub(crate) static SYSTEMD_SERVICE: &Object = &Object {
     service: SYSTEMD.service,
     path: "/",
     interface: "org.freedesktop.systemd1.Service",
 };
 
-static SYSTEMD_UNIT: &Object = &Object {
+pub(crate) static SYSTEMD_UNIT: &Object = &Object {
     service: SYSTEMD.service,
     path: "/",
     interface: "org.freedesktop.systemd1.Unit",
 };
+
+pub(crate) static SYSTEMD_TIMER: &Object = &Object {
+    service: SYSTEMD.service,
+    path: "/",
+    interface: "org.freedesktop.systemd1.Timer",
+};
@@ -0,0 +1,258 @@
+use anyhow::{anyhow, Result};
+use std::{fmt, str::FromStr, time::Duration};
+
+const USEC_PER_MSEC: u64 = 1_000;
+const USEC_PER_SEC: u64 = 1_000_000;
+const USEC_PER_MINUTE: u64 = 60 * USEC_PER_SEC;
+const USEC_PER_HOUR: u64 = 60 * USEC_PER_MINUTE;
+const USEC_PER_DAY: u64 = 24 * USEC_PER_HOUR;
+const USEC_PER_WEEK: u64 = 7 * USEC_PER_DAY;
+const USEC_PER_MONTH: u64 = 2_629_800 * USEC_PER_SEC;
+const USEC_PER_YEAR: u64 = 31_557_600 * USEC_PER_SEC;
+
+// Same table and order as systemd's parse_sec(); matching is case sensitive (M is months, m minutes).
+const UNITS: &[(&str, u64)] = &[
+    ("seconds", USEC_PER_SEC),
+    ("second", USEC_PER_SEC),
+    ("sec", USEC_PER_SEC),
+    ("s", USEC_PER_SEC),
+    ("minutes", USEC_PER_MINUTE),
+    ("minute", USEC_PER_MINUTE),
+    ("min", USEC_PER_MINUTE),
+    ("months", USEC_PER_MONTH),
+    ("month", USEC_PER_MONTH),
+    ("M", USEC_PER_MONTH),
+    ("msec", USEC_PER_MSEC),
+    ("ms", USEC_PER_MSEC),
+    ("m", USEC_PER_MINUTE),
+    ("hours", USEC_PER_HOUR),
+    ("hour", USEC_PER_HOUR),
+    ("hr", USEC_PER_HOUR),
+    ("h", USEC_PER_HOUR),
+    ("days", USEC_PER_DAY),
+    ("day", USEC_PER_DAY),
+    ("d", USEC_PER_DAY),
+    ("weeks", USEC_PER_WEEK),
+    ("week", USEC_PER_WEEK),
+    ("w", USEC_PER_WEEK),
+    ("years", USEC_PER_YEAR),
+    ("year", USEC_PER_YEAR),
+    ("y", USEC_PER_YEAR),
+    ("usec", 1),
+    ("us", 1),
+    ("μs", 1),
+    ("µs", 1),
+];
+
+// Output units of systemd's format_timespan().
+const FORMAT_UNITS: &[(&str, u64)] = &[
+    ("y", USEC_PER_YEAR),
+    ("month", USEC_PER_MONTH),
+    ("w", USEC_PER_WEEK),
+    ("d", USEC_PER_DAY),
+    ("h", USEC_PER_HOUR),
+    ("min", USEC_PER_MINUTE),
+    ("s", USEC_PER_SEC),
+    ("ms", USEC_PER_MSEC),
+    ("us", 1),
+];
+
+/// A systemd time span such as `1h 30min` or `5s`, kept in microseconds like systemd's usec_t.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct TimeSpan {
+    usec: u64,
+}
+
+impl TimeSpan {
+    pub const INFINITY: TimeSpan = TimeSpan { usec: u64::MAX };
+
+    pub fn from_usec(usec: u64) -> Self {
+        Self { usec }
+    }
+
+    pub fn as_usec(&self) -> u64 {
+        self.usec
+    }
+
+    pub fn is_infinity(&self) -> bool {
+        self.usec == u64::MAX
+    }
+
+    /// `None` for infinity.
+    pub fn as_duration(&self) -> Option<Duration> {
+        if self.is_infinity() {
+            None
+        } else {
+            Some(Duration::from_micros(self.usec))
+        }
+    }
+}
+
+impl From<Duration> for TimeSpan {
+    fn from(duration: Duration) -> Self {
+        Self {
+            usec: duration.as_micros().min(u64::MAX as u128 - 1) as u64,
+        }
+    }
+}
+
+impl FromStr for TimeSpan {
+    type Err = anyhow::Error;
+
+    /// Parses like `systemd-analyze timespan`: a bare number is seconds and values may be fractional (`1.5h`).
+    fn from_str(s: &str) -> Result<Self> {
+        let trimmed = s.trim();
+        if trimmed == "infinity" {
+            return Ok(TimeSpan::INFINITY);
+        }
+        if trimmed.is_empty() {
+            return Err(anyhow!("empty time span"));
+        }
+
+        let mut usec: u64 = 0;
+        let mut rest = trimmed;
+        while !rest.is_empty() {
+            let number_len = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            let number = &rest[..number_len];
+            if number.is_empty() || number.starts_with('.') {
+                return Err(anyhow!("invalid time span {}: expected a number at {}", s, rest));
+            }
+            rest = rest[number_len..].trim_start();
+
+            let unit_len = rest
+                .find(|c: char| c.is_ascii_digit() || c.is_whitespace() || c == '.')
+                .unwrap_or(rest.len());
+            let unit = &rest[..unit_len];
+            let multiplier = match unit {
+                "" => USEC_PER_SEC,
+                unit => match UNITS.iter().find(|(name, _)| *name == unit) {
+                    Some((_, multiplier)) => *multiplier,
+                    None => return Err(anyhow!("invalid time span {}: unknown unit {}", s, unit)),
+                },
+            };
+            rest = rest[unit_len..].trim_start();
+
+            let (whole, fraction) = match number.find('.') {
+                Some(dot) => (&number[..dot], &number[dot + 1..]),
+                None => (number, ""),
+            };
+            let whole: u64 = whole
+                .parse()
+                .map_err(|_| anyhow!("invalid time span {}: bad number {}", s, number))?;
+            let mut value = whole
+                .checked_mul(multiplier)
+                .ok_or_else(|| anyhow!("time span {} is out of range", s))?;
+            let mut scale = multiplier;
+            for digit in fraction.chars() {
+                let digit = digit
+                    .to_digit(10)
+                    .ok_or_else(|| anyhow!("invalid time span {}: bad number {}", s, number))?;
+                scale /= 10;
+                value += digit as u64 * scale;
+            }
+            usec = usec
+                .checked_add(value)
+                .filter(|usec| *usec != u64::MAX)
+                .ok_or_else(|| anyhow!("time span {} is out of range", s))?;
+        }
+
+        Ok(TimeSpan { usec })
+    }
+}
+
+impl TimeSpan {
+    /// Formats like systemd's format_timespan(), leaving out what is below `accuracy`; `1.234s` at 1ms.
+    pub fn format(&self, accuracy: Duration) -> String {
+        if self.is_infinity() {
+            return "infinity".to_string();
+        }
+        if self.usec == 0 {
+            return "0".to_string();
+        }
+
+        let accuracy = (accuracy.as_micros() as u64).max(1);
+        let mut t = self.usec;
+        let mut parts: Vec<String> = vec![];
+        for (suffix, unit) in FORMAT_UNITS.iter() {
+            if t == 0 || (t < accuracy && !parts.is_empty()) {
+                break;
+            }
+            if t < *unit {
+                continue;
+            }
+            let a = t / unit;
+            let mut b = t % unit;
+            // Below a minute, a remainder is shown as a decimal fraction of the current unit.
+            if t < USEC_PER_MINUTE && b > 0 {
+                let mut digits = unit.to_string().len() as i32 - 1;
+                let mut cc = accuracy;
+                while cc > 1 {
+                    b /= 10;
+                    digits -= 1;
+                    cc /= 10;
+                }
+                if digits > 0 {
+                    parts.push(format!("{}.{:0width$}{}", a, b, suffix, width = digits as usize));
+                    break;
+                }
+            }
+            parts.push(format!("{}{}", a, suffix));
+            t %= unit;
+        }
+        parts.join(" ")
+    }
+}
+
+impl fmt::Display for TimeSpan {
+    /// Normalized the way `systemd-analyze timespan` prints the "Human:" line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(Duration::from_micros(1)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::TimeSpan;
+
+    fn normalize(s: &str) -> String {
+        s.parse::<TimeSpan>().unwrap().to_string()
+    }
+
+    #[test]
+    fn parses_time_spans() {
+        assert_eq!("5s".parse::<TimeSpan>().unwrap().as_usec(), 5_000_000);
+        assert_eq!("1h 30min".parse::<TimeSpan>().unwrap().as_usec(), 5_400_000_000);
+        assert_eq!("1h30m".parse::<TimeSpan>().unwrap().as_usec(), 5_400_000_000);
+        assert_eq!("2.5ms".parse::<TimeSpan>().unwrap().as_usec(), 2_500);
+        assert_eq!("300".parse::<TimeSpan>().unwrap().as_usec(), 300_000_000);
+        assert!("infinity".parse::<TimeSpan>().unwrap().is_infinity());
+        assert!("5 parsecs".parse::<TimeSpan>().is_err());
+        assert!("".parse::<TimeSpan>().is_err());
+    }
+
+    #[test]
+    fn normalizes_like_systemd_analyze() {
+        assert_eq!(normalize("90s"), "1min 30s");
+        assert_eq!(normalize("1h 30min"), "1h 30min");
+        assert_eq!(normalize("1.5s"), "1.500000s");
+        assert_eq!(normalize("2d 3h"), "2d 3h");
+        assert_eq!(normalize("1500us"), "1.500ms");
+        assert_eq!(normalize("0"), "0");
+    }
+
+    #[test]
+    fn formats_with_accuracy() {
+        let cpu = TimeSpan::from_usec(1_234_567);
+        assert_eq!(cpu.format(Duration::from_millis(1)), "1.234s");
+        assert_eq!(cpu.format(Duration::from_secs(1)), "1s");
+        assert_eq!(
+            TimeSpan::from_usec(90_500_000).format(Duration::from_secs(1)),
+            "1min 30s"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use dbus::arg;
+use std::time::Duration;
+use tracing::debug;
+
+use super::{
+    property_bool, property_str, property_timestamp, Mode, Systemd1Manager, SystemdManager, TransientUnitBuilder,
+    SYSTEMD_TIMER, SYSTEMD_UNIT,
+};
+
+/// When a scheduled command fires; the transient-timer equivalent of a crontab line.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Schedule {
+    pub on_calendar: Vec<String>,
+    pub on_active: Option<Duration>,
+    pub on_unit_active: Option<Duration>,
+    pub persistent: bool,
+    pub randomized_delay: Option<Duration>,
+    pub accuracy: Option<Duration>,
+}
+
+/// A transient timer and the service it triggers.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScheduledJob {
+    pub timer: String,
+    pub service: String,
+    pub description: String,
+    /// Next elapse in microseconds since the epoch, if the timer has a calendar trigger pending.
+    pub next_elapse_usec: Option<u64>,
+    /// Last time the timer fired, in microseconds since the epoch.
+    pub last_trigger_usec: Option<u64>,
+}
+
+impl ScheduledJob {
+    /// Builds the job from the timer's Unit and Timer interface properties.
+    fn from_properties(timer: &str, unit: &arg::PropMap, properties: &arg::PropMap) -> Self {
+        ScheduledJob {
+            timer: timer.to_string(),
+            service: property_str(properties, "Unit").unwrap_or_default().to_string(),
+            description: property_str(unit, "Description").unwrap_or_default().to_string(),
+            next_elapse_usec: property_timestamp(properties, "NextElapseUSecRealtime"),
+            last_trigger_usec: property_timestamp(properties, "LastTriggerUSec"),
+        }
+    }
+}
+
+impl SystemdManager {
+    /// Creates `<name>.timer` together with `service` as an aux unit, like `systemd-run --on-calendar`.
+    pub async fn schedule_transient(
+        &self,
+        service: TransientUnitBuilder,
+        schedule: Schedule,
+    ) -> Result<ScheduledJob, dbus::MethodErr> {
+        let service_name = service.name().to_string();
+        let timer_name = match service_name.strip_suffix(".service") {
+            Some(prefix) => format!("{}.timer", prefix),
+            None => return Err(dbus::MethodErr::invalid_arg(&service_name)),
+        };
+
+        let mut timer = TransientUnitBuilder::new(&timer_name).persistent(schedule.persistent);
+        for spec in schedule.on_calendar.iter() {
+            timer = timer.on_calendar(spec);
+        }
+        if let Some(after) = schedule.on_active {
+            timer = timer.on_active(after);
+        }
+        if let Some(after) = schedule.on_unit_active {
+            timer = timer.on_unit_active(after);
+        }
+        if let Some(delay) = schedule.randomized_delay {
+            timer = timer.randomized_delay(delay);
+        }
+        if let Some(accuracy) = schedule.accuracy {
+            timer = timer.accuracy(accuracy);
+        }
+        let timer = timer.aux(service).build()?;
+
+        let result = self.await_job(timer.start(self, &Mode::Fail)).await?;
+        if result != "done" {
+            let message = format!("start job for {} finished with result {}", timer_name, result);
+            return Err(dbus::MethodErr::failed(&message));
+        }
+        self.scheduled_job(&timer_name).await
+    }
+
+    /// Transient timers currently loaded, whether created by `schedule_transient` or `systemd-run`.
+    pub async fn list_scheduled(&self) -> Result<Vec<ScheduledJob>, dbus::MethodErr> {
+        let mut scheduled = vec![];
+        for unit in self.list_units_by_patterns(vec![], vec!["*.timer"]).await? {
+            // A timer may be unloaded between the listing and these reads; leave it out rather than fail.
+            let unit_properties = match self.get_unit_properties(&unit.0, SYSTEMD_UNIT.interface).await {
+                Ok(properties) => properties,
+                Err(e) => {
+                    debug!("{}: {:?}", unit.0, e);
+                    continue;
+                }
+            };
+            if property_bool(&unit_properties, "Transient") != Some(true) {
+                continue;
+            }
+            match self.get_unit_properties(&unit.0, SYSTEMD_TIMER.interface).await {
+                Ok(properties) => scheduled.push(ScheduledJob::from_properties(&unit.0, &unit_properties, &properties)),
+                Err(e) => debug!("{}: {:?}", unit.0, e),
+            }
+        }
+        Ok(scheduled)
+    }
+
+    /// Stops a scheduled timer and its service; transient units are unloaded once inactive.
+    pub async fn cancel_scheduled(&self, timer: &str) -> Result<(), dbus::MethodErr> {
+        let job = self.scheduled_job(timer).await?;
+        self.await_job(self.stop_unit(&job.timer, &Mode::Replace)).await?;
+        self.await_job(self.stop_unit(&job.service, &Mode::Replace)).await?;
+        // Failed units stay loaded until their failure is reset.
+        let _ = self.reset_failed_unit(&job.service).await;
+        let _ = self.reset_failed_unit(&job.timer).await;
+        Ok(())
+    }
+
+    async fn scheduled_job(&self, timer: &str) -> Result<ScheduledJob, dbus::MethodErr> {
+        let unit = self.get_unit_properties(timer, SYSTEMD_UNIT.interface).await?;
+        let properties = self.get_unit_properties(timer, SYSTEMD_TIMER.interface).await?;
+        Ok(ScheduledJob::from_properties(timer, &unit, &properties))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::{PropMap, RefArg, Variant};
+
+    use super::ScheduledJob;
+
+    fn properties(entries: Vec<(&str, Box<dyn RefArg>)>) -> PropMap {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), Variant(value)))
+            .collect()
+    }
+
+    #[test]
+    fn scheduled_jobs_are_read_from_timer_properties() {
+        let unit = properties(vec![("Description", Box::new("nightly backup".to_string()))]);
+        let timer = properties(vec![
+            ("Unit", Box::new("backup.service".to_string())),
+            ("NextElapseUSecRealtime", Box::new(1_700_000_000_000_000u64)),
+            ("LastTriggerUSec", Box::new(0u64)),
+        ]);
+
+        let job = ScheduledJob::from_properties("backup.timer", &unit, &timer);
+        assert_eq!(job.timer, "backup.timer");
+        assert_eq!(job.service, "backup.service");
+        assert_eq!(job.description, "nightly backup");
+        assert_eq!(job.next_elapse_usec, Some(1_700_000_000_000_000));
+        // A timer that never fired reports 0.
+        assert_eq!(job.last_trigger_usec, None);
+    }
+}
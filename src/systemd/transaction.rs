@@ -0,0 +1,456 @@
+use std::fmt;
+use tracing::warn;
+
+use super::{
+    cgroup_interface, property_str, Mode, ResourceControl, ResourceProperties, Systemd1Manager, SystemdManager,
+    ENABLED_STATES, RUNNING_STATES, SYSTEMD_UNIT,
+};
+
+#[derive(Clone, Debug)]
+enum Step {
+    Start(String),
+    Stop(String),
+    Enable(String),
+    Disable(String),
+    ResourceControl {
+        unit: String,
+        control: ResourceControl,
+        runtime: bool,
+    },
+}
+
+/// A change a transaction applies, or replays to undo one.
+#[derive(Clone, Debug, PartialEq)]
+enum Operation {
+    Start(String),
+    Stop(String),
+    Enable(String),
+    Disable(String),
+    SetProperties {
+        unit: String,
+        properties: ResourceProperties,
+        runtime: bool,
+    },
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Start(unit) => write!(f, "start {}", unit),
+            Operation::Stop(unit) => write!(f, "stop {}", unit),
+            Operation::Enable(unit) => write!(f, "enable {}", unit),
+            Operation::Disable(unit) => write!(f, "disable {}", unit),
+            Operation::SetProperties { unit, properties, .. } => write!(f, "set-property {} {}", unit, properties),
+        }
+    }
+}
+
+/// The operation undoing `operation` on a unit whose active or unit file state was `prior`; `None` if it
+/// changes nothing from there.
+fn inverse(operation: &Operation, prior: &str) -> Option<Operation> {
+    let running = RUNNING_STATES.contains(&prior);
+    match operation {
+        Operation::Start(unit) if !running => Some(Operation::Stop(unit.clone())),
+        Operation::Stop(unit) if running => Some(Operation::Start(unit.clone())),
+        Operation::Enable(unit) if !ENABLED_STATES.contains(&prior) => Some(Operation::Disable(unit.clone())),
+        Operation::Disable(unit) if prior == "enabled" => Some(Operation::Enable(unit.clone())),
+        _ => None,
+    }
+}
+
+/// Changes to several units, applied in order by [`SystemdManager::run_transaction`]. The state each step
+/// changes is recorded just before it runs, and if a step fails the steps up to and including it are undone in
+/// reverse order.
+///
+/// Unit file changes are made persistently and followed by a daemon reload; jobs are queued in `replace` mode
+/// and awaited.
+#[derive(Clone, Debug, Default)]
+pub struct Transaction {
+    steps: Vec<Step>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(mut self, name: &str) -> Self {
+        self.steps.push(Step::Start(name.to_string()));
+        self
+    }
+
+    pub fn stop(mut self, name: &str) -> Self {
+        self.steps.push(Step::Stop(name.to_string()));
+        self
+    }
+
+    pub fn enable(mut self, name: &str) -> Self {
+        self.steps.push(Step::Enable(name.to_string()));
+        self
+    }
+
+    pub fn disable(mut self, name: &str) -> Self {
+        self.steps.push(Step::Disable(name.to_string()));
+        self
+    }
+
+    /// Applies `control` like [`SystemdManager::set_resource_control`]; rolling back sets the previous values
+    /// the same way.
+    pub fn set_resource_control(mut self, name: &str, control: ResourceControl, runtime: bool) -> Self {
+        self.steps.push(Step::ResourceControl {
+            unit: name.to_string(),
+            control,
+            runtime,
+        });
+        self
+    }
+
+    fn operations(&self) -> Result<Vec<Operation>, dbus::MethodErr> {
+        self.steps
+            .iter()
+            .cloned()
+            .map(|step| {
+                Ok(match step {
+                    Step::Start(unit) => Operation::Start(unit),
+                    Step::Stop(unit) => Operation::Stop(unit),
+                    Step::Enable(unit) => Operation::Enable(unit),
+                    Step::Disable(unit) => Operation::Disable(unit),
+                    Step::ResourceControl { unit, control, runtime } => Operation::SetProperties {
+                        unit,
+                        properties: control.build()?,
+                        runtime,
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Undo {
+    /// Like `stop web.service`.
+    pub operation: String,
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_method_result"))]
+    pub result: Result<(), dbus::MethodErr>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StepReport {
+    /// Like `start web.service`.
+    pub step: String,
+    /// The active state, unit file state or property values recorded before the step ran.
+    pub prior: Option<String>,
+    /// `None` if the step didn't run because an earlier one failed.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_method_result::option"))]
+    pub result: Option<Result<(), dbus::MethodErr>>,
+    /// What was replayed to undo the step; `None` if there was no failure or the step changed nothing.
+    pub rollback: Option<Undo>,
+}
+
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransactionReport {
+    /// One per step, in the order they were added.
+    pub steps: Vec<StepReport>,
+}
+
+impl TransactionReport {
+    pub fn succeeded(&self) -> bool {
+        self.steps.iter().all(|step| matches!(step.result, Some(Ok(()))))
+    }
+
+    /// The steps undone after a failure, in the order they were rolled back.
+    pub fn rolled_back(&self) -> impl Iterator<Item = &StepReport> {
+        self.steps.iter().rev().filter(|step| step.rollback.is_some())
+    }
+}
+
+impl fmt::Display for TransactionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in self.steps.iter() {
+            write!(f, "{}", step.step)?;
+            if let Some(prior) = &step.prior {
+                write!(f, " (was {})", prior)?;
+            }
+            match &step.result {
+                Some(Ok(())) => f.write_str(": done")?,
+                Some(Err(e)) => write!(f, ": failed: {}", e.description())?,
+                None => f.write_str(": not run")?,
+            }
+            match &step.rollback {
+                Some(Undo {
+                    operation,
+                    result: Ok(()),
+                }) => write!(f, ", rolled back with {}", operation)?,
+                Some(Undo {
+                    operation,
+                    result: Err(e),
+                }) => write!(f, ", rollback with {} failed: {}", operation, e.description())?,
+                None => {}
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl SystemdManager {
+    /// Runs the steps of `transaction` in order until one fails, then replays the inverse of each step that ran,
+    /// the failed one included since it may have partly taken effect, newest first. Invalid resource-control
+    /// values fail the whole transaction before anything is changed.
+    pub async fn run_transaction(&self, transaction: &Transaction) -> Result<TransactionReport, dbus::MethodErr> {
+        let operations = transaction.operations()?;
+        let mut report = TransactionReport {
+            steps: operations
+                .iter()
+                .map(|operation| StepReport {
+                    step: operation.to_string(),
+                    prior: None,
+                    result: None,
+                    rollback: None,
+                })
+                .collect(),
+        };
+
+        let mut inverses: Vec<(usize, Operation)> = vec![];
+        let mut failed = false;
+        for (i, operation) in operations.iter().enumerate() {
+            let step = &mut report.steps[i];
+            let (prior, inverse) = match self.record(operation).await {
+                Ok(recorded) => recorded,
+                Err(e) => {
+                    step.result = Some(Err(e));
+                    failed = true;
+                    break;
+                }
+            };
+            step.prior = Some(prior);
+            if let Some(inverse) = inverse {
+                inverses.push((i, inverse));
+            }
+            let result = self.perform(operation).await;
+            failed = result.is_err();
+            step.result = Some(result);
+            if failed {
+                break;
+            }
+        }
+
+        if failed {
+            for (i, inverse) in inverses.into_iter().rev() {
+                let result = self.perform(&inverse).await;
+                if let Err(e) = &result {
+                    warn!("rolling back {} with {} failed: {:?}", report.steps[i].step, inverse, e);
+                }
+                report.steps[i].rollback = Some(Undo {
+                    operation: inverse.to_string(),
+                    result,
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    /// The state `operation` changes, and the operation setting it back.
+    async fn record(&self, operation: &Operation) -> Result<(String, Option<Operation>), dbus::MethodErr> {
+        let prior = match operation {
+            Operation::Start(unit) | Operation::Stop(unit) => {
+                let properties = self.get_unit_properties(unit, SYSTEMD_UNIT.interface).await?;
+                property_str(&properties, "ActiveState")
+                    .unwrap_or("inactive")
+                    .to_string()
+            }
+            Operation::Enable(unit) | Operation::Disable(unit) => self.get_unit_file_state(unit).await?,
+            Operation::SetProperties {
+                unit,
+                properties,
+                runtime,
+            } => {
+                let interface = cgroup_interface(unit)
+                    .ok_or_else(|| dbus::MethodErr::invalid_arg(&format!("{} has no cgroup to control", unit)))?;
+                let current = self.get_unit_properties(unit, interface).await?;
+                let previous = properties.previous(&current);
+                return Ok((
+                    previous.to_string(),
+                    Some(Operation::SetProperties {
+                        unit: unit.clone(),
+                        properties: previous,
+                        runtime: *runtime,
+                    }),
+                ));
+            }
+        };
+        let inverse = inverse(operation, &prior);
+        Ok((prior, inverse))
+    }
+
+    async fn perform(&self, operation: &Operation) -> Result<(), dbus::MethodErr> {
+        let mode = &Mode::Replace;
+        let job_result = match operation {
+            Operation::Start(unit) => self.await_job(self.start_unit(unit, mode)).await?,
+            Operation::Stop(unit) => self.await_job(self.stop_unit(unit, mode)).await?,
+            Operation::Enable(unit) => {
+                self.enable_unit_files(vec![unit], false, false).await?;
+                return self.reload().await;
+            }
+            Operation::Disable(unit) => {
+                self.disable_unit_files(vec![unit], false).await?;
+                return self.reload().await;
+            }
+            Operation::SetProperties {
+                unit,
+                properties,
+                runtime,
+            } => return self.set_resource_properties(unit, properties, *runtime).await,
+        };
+        if job_result == "done" {
+            Ok(())
+        } else {
+            Err(dbus::MethodErr::failed(&format!("{} job {}", operation, job_result)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::{PropMap, RefArg, Variant};
+
+    use super::{inverse, Operation, StepReport, TransactionReport, Undo};
+    use crate::{ResourceControl, ResourceValue};
+
+    #[test]
+    fn inverts_only_what_changes() {
+        let unit = || "web.service".to_string();
+        assert_eq!(
+            inverse(&Operation::Start(unit()), "inactive"),
+            Some(Operation::Stop(unit()))
+        );
+        assert_eq!(
+            inverse(&Operation::Start(unit()), "failed"),
+            Some(Operation::Stop(unit()))
+        );
+        assert_eq!(inverse(&Operation::Start(unit()), "active"), None);
+        assert_eq!(
+            inverse(&Operation::Stop(unit()), "active"),
+            Some(Operation::Start(unit()))
+        );
+        assert_eq!(inverse(&Operation::Stop(unit()), "inactive"), None);
+        assert_eq!(
+            inverse(&Operation::Enable(unit()), "disabled"),
+            Some(Operation::Disable(unit()))
+        );
+        assert_eq!(inverse(&Operation::Enable(unit()), "static"), None);
+        assert_eq!(
+            inverse(&Operation::Disable(unit()), "enabled"),
+            Some(Operation::Enable(unit()))
+        );
+        assert_eq!(inverse(&Operation::Disable(unit()), "disabled"), None);
+    }
+
+    #[test]
+    fn records_previous_property_values() {
+        let properties = ResourceControl::new()
+            .memory_max("50%")
+            .cpu_weight(500)
+            .io_read_bandwidth_max("/dev/sdb", "10M")
+            .build()
+            .unwrap();
+        let mut current = PropMap::new();
+        current.insert("MemoryMax".to_string(), Variant(Box::new(u64::MAX) as Box<dyn RefArg>));
+        current.insert("CPUWeight".to_string(), Variant(Box::new(100u64) as Box<dyn RefArg>));
+        current.insert(
+            "IOReadBandwidthMax".to_string(),
+            Variant(Box::new(vec![("/dev/sda".to_string(), 5_000_000u64)]) as Box<dyn RefArg>),
+        );
+
+        let previous = properties.previous(&current);
+        assert_eq!(
+            previous.properties,
+            vec![
+                ("MemoryMax".to_string(), ResourceValue::U64(u64::MAX)),
+                ("CPUWeight".to_string(), ResourceValue::U64(100)),
+                (
+                    "IOReadBandwidthMax".to_string(),
+                    ResourceValue::Devices(vec![
+                        ("/dev/sda".to_string(), 5_000_000),
+                        ("/dev/sdb".to_string(), u64::MAX)
+                    ])
+                ),
+            ]
+        );
+        assert_eq!(
+            previous.to_string(),
+            "MemoryMax=infinity CPUWeight=100 IOReadBandwidthMax=/dev/sda 5000000,/dev/sdb infinity"
+        );
+    }
+
+    #[test]
+    fn reports_rolled_back_steps() {
+        let step = |step: &str, prior: Option<&str>, result, rollback: Option<&str>| StepReport {
+            step: step.to_string(),
+            prior: prior.map(String::from),
+            result,
+            rollback: rollback.map(|operation| Undo {
+                operation: operation.to_string(),
+                result: Ok(()),
+            }),
+        };
+        let report = TransactionReport {
+            steps: vec![
+                step("stop a.service", Some("active"), Some(Ok(())), Some("start a.service")),
+                step("start b.service", Some("active"), Some(Ok(())), None),
+                step(
+                    "start c.service",
+                    Some("inactive"),
+                    Some(Err(dbus::MethodErr::failed(&"start c.service job failed"))),
+                    Some("stop c.service"),
+                ),
+                step("enable d.service", None, None, None),
+            ],
+        };
+        assert!(!report.succeeded());
+        let rolled_back: Vec<&str> = report.rolled_back().map(|step| step.step.as_str()).collect();
+        assert_eq!(rolled_back, vec!["start c.service", "stop a.service"]);
+        assert_eq!(
+            report.to_string(),
+            "stop a.service (was active): done, rolled back with start a.service\n\
+             start b.service (was active): done\n\
+             start c.service (was inactive): failed: start c.service job failed, rolled back with stop c.service\n\
+             enable d.service: not run\n"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializes_step_errors_by_name_and_message() {
+        let report = TransactionReport {
+            steps: vec![StepReport {
+                step: "start c.service".to_string(),
+                prior: Some("inactive".to_string()),
+                result: Some(Err(dbus::MethodErr::failed(&"start c.service job failed"))),
+                rollback: Some(Undo {
+                    operation: "stop c.service".to_string(),
+                    result: Ok(()),
+                }),
+            }],
+        };
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            json["steps"][0]["result"]["Err"]["name"],
+            "org.freedesktop.DBus.Error.Failed"
+        );
+        assert_eq!(
+            json["steps"][0]["result"]["Err"]["message"],
+            "start c.service job failed"
+        );
+        assert_eq!(json["steps"][0]["rollback"]["result"]["Ok"], serde_json::Value::Null);
+
+        let parsed: TransactionReport = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.to_string(), report.to_string());
+        let not_run: StepReport =
+            serde_json::from_str(r#"{"step":"enable d.service","prior":null,"result":null,"rollback":null}"#).unwrap();
+        assert!(not_run.result.is_none());
+    }
+}
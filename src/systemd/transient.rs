@@ -0,0 +1,673 @@
+use dbus::arg::{self, RefArg, Variant};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use strum::{AsRefStr, AsStaticStr, IntoStaticStr};
+
+use super::{CalendarSpec, Mode, Systemd1Manager, TimeSpan};
+
+pub type PropertyDto = (String, Variant<Box<dyn RefArg>>);
+
+pub type AuxUnitDto = (String, Vec<PropertyDto>);
+
+pub type PropertyArgs<'a> = Vec<(&'a str, Variant<Box<dyn RefArg>>)>;
+
+pub type AuxUnitArgs<'a> = Vec<(&'a str, PropertyArgs<'a>)>;
+
+const UNIT_TYPES: &[&str] = &[
+    "service",
+    "socket",
+    "target",
+    "device",
+    "mount",
+    "automount",
+    "swap",
+    "timer",
+    "path",
+    "slice",
+    "scope",
+];
+
+#[derive(AsRefStr, AsStaticStr, IntoStaticStr, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum ServiceType {
+    Simple,
+    Exec,
+    Forking,
+    Oneshot,
+    Dbus,
+    Notify,
+    Idle,
+}
+
+/// Dependency settings; serialized under their unit-file names (After=, Wants=, ...).
+#[derive(AsRefStr, AsStaticStr, IntoStaticStr, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Dependency {
+    After,
+    Before,
+    Wants,
+    Requires,
+    Requisite,
+    BindsTo,
+    PartOf,
+    Conflicts,
+}
+
+/// A single ExecStart= entry; the first argv element is also used as the binary path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExecCommand {
+    pub path: String,
+    pub argv: Vec<String>,
+    pub ignore_failure: bool,
+}
+
+impl ExecCommand {
+    pub fn new<I, S>(argv: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let argv: Vec<String> = argv.into_iter().map(Into::into).collect();
+        Self {
+            path: argv.first().cloned().unwrap_or_default(),
+            argv,
+            ignore_failure: false,
+        }
+    }
+
+    /// Equivalent of the `-` prefix in unit files: a non-zero exit status is not considered a failure.
+    pub fn ignore_failure(mut self, ignore_failure: bool) -> Self {
+        self.ignore_failure = ignore_failure;
+        self
+    }
+}
+
+/// Builds the property list for `StartTransientUnit`, the way `systemd-run` does.
+#[derive(Debug, Default)]
+pub struct TransientUnitBuilder {
+    name: String,
+    description: Option<String>,
+    exec_start: Vec<ExecCommand>,
+    environment: Vec<String>,
+    working_directory: Option<String>,
+    user: Option<String>,
+    group: Option<String>,
+    dynamic_user: Option<bool>,
+    cpu_quota_percent: Option<u64>,
+    cpu_weight: Option<u64>,
+    memory_max: Option<u64>,
+    memory_high: Option<u64>,
+    memory_low: Option<u64>,
+    tasks_max: Option<u64>,
+    io_weight: Option<u64>,
+    remain_after_exit: Option<bool>,
+    service_type: Option<ServiceType>,
+    slice: Option<String>,
+    dependencies: Vec<(Dependency, String)>,
+    pids: Vec<u32>,
+    timers_calendar: Vec<(String, String)>,
+    timers_monotonic: Vec<(String, u64)>,
+    persistent: Option<bool>,
+    randomized_delay: Option<TimeSpan>,
+    accuracy: Option<TimeSpan>,
+    properties: Vec<PropertyDto>,
+    aux: Vec<TransientUnitBuilder>,
+}
+
+impl TransientUnitBuilder {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// A fresh `run-r<id>.<unit_type>` name, the scheme `systemd-run` uses.
+    pub fn with_generated_name(unit_type: &str) -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        Self::new(&format!("run-r{:x}{:x}.{}", std::process::id(), nanos, unit_type))
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    pub fn exec_start(mut self, command: ExecCommand) -> Self {
+        self.exec_start.push(command);
+        self
+    }
+
+    pub fn environment(mut self, key: &str, value: &str) -> Self {
+        self.environment.push(format!("{}={}", key, value));
+        self
+    }
+
+    pub fn working_directory(mut self, path: &str) -> Self {
+        self.working_directory = Some(path.to_string());
+        self
+    }
+
+    pub fn user(mut self, user: &str) -> Self {
+        self.user = Some(user.to_string());
+        self
+    }
+
+    pub fn group(mut self, group: &str) -> Self {
+        self.group = Some(group.to_string());
+        self
+    }
+
+    pub fn dynamic_user(mut self, dynamic_user: bool) -> Self {
+        self.dynamic_user = Some(dynamic_user);
+        self
+    }
+
+    /// CPU time relative to one CPU, e.g. 150 for 1.5 CPUs; sent as CPUQuotaPerSecUSec.
+    pub fn cpu_quota_percent(mut self, percent: u64) -> Self {
+        self.cpu_quota_percent = Some(percent);
+        self
+    }
+
+    pub fn cpu_weight(mut self, weight: u64) -> Self {
+        self.cpu_weight = Some(weight);
+        self
+    }
+
+    pub fn memory_max(mut self, bytes: u64) -> Self {
+        self.memory_max = Some(bytes);
+        self
+    }
+
+    pub fn memory_high(mut self, bytes: u64) -> Self {
+        self.memory_high = Some(bytes);
+        self
+    }
+
+    pub fn memory_low(mut self, bytes: u64) -> Self {
+        self.memory_low = Some(bytes);
+        self
+    }
+
+    pub fn tasks_max(mut self, tasks: u64) -> Self {
+        self.tasks_max = Some(tasks);
+        self
+    }
+
+    pub fn io_weight(mut self, weight: u64) -> Self {
+        self.io_weight = Some(weight);
+        self
+    }
+
+    pub fn remain_after_exit(mut self, remain_after_exit: bool) -> Self {
+        self.remain_after_exit = Some(remain_after_exit);
+        self
+    }
+
+    pub fn service_type(mut self, service_type: ServiceType) -> Self {
+        self.service_type = Some(service_type);
+        self
+    }
+
+    pub fn slice(mut self, slice: &str) -> Self {
+        self.slice = Some(slice.to_string());
+        self
+    }
+
+    pub fn dependency(mut self, dependency: Dependency, unit: &str) -> Self {
+        self.dependencies.push((dependency, unit.to_string()));
+        self
+    }
+
+    /// Processes to move into a scope unit.
+    pub fn pids(mut self, pids: Vec<u32>) -> Self {
+        self.pids.extend(pids);
+        self
+    }
+
+    pub(crate) fn has_pids(&self) -> bool {
+        !self.pids.is_empty()
+    }
+
+    /// Calendar event the timer elapses on, e.g. `Mon..Fri *-*-* 09:00:00`; may be given more than once.
+    pub fn on_calendar(mut self, spec: &str) -> Self {
+        self.timers_calendar.push(("OnCalendar".to_string(), spec.to_string()));
+        self
+    }
+
+    /// Elapse this long after the timer itself is started.
+    pub fn on_active(mut self, after: impl Into<TimeSpan>) -> Self {
+        self.timers_monotonic
+            .push(("OnActiveUSec".to_string(), after.into().as_usec()));
+        self
+    }
+
+    /// Elapse this long after the triggered unit was last activated.
+    pub fn on_unit_active(mut self, after: impl Into<TimeSpan>) -> Self {
+        self.timers_monotonic
+            .push(("OnUnitActiveUSec".to_string(), after.into().as_usec()));
+        self
+    }
+
+    /// Catch up on calendar events missed while the timer wasn't running.
+    pub fn persistent(mut self, persistent: bool) -> Self {
+        self.persistent = Some(persistent);
+        self
+    }
+
+    pub fn randomized_delay(mut self, delay: impl Into<TimeSpan>) -> Self {
+        self.randomized_delay = Some(delay.into());
+        self
+    }
+
+    pub fn accuracy(mut self, accuracy: impl Into<TimeSpan>) -> Self {
+        self.accuracy = Some(accuracy.into());
+        self
+    }
+
+    /// Escape hatch for properties without a typed setter; the value must match systemd's D-Bus type for it.
+    pub fn property<T: RefArg + 'static>(mut self, name: &str, value: T) -> Self {
+        self.properties.push((name.to_string(), Variant(Box::new(value))));
+        self
+    }
+
+    /// Adds a unit created in the same transaction, e.g. the service triggered by a transient timer.
+    pub fn aux(mut self, unit: TransientUnitBuilder) -> Self {
+        self.aux.push(unit);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn unit_type(&self) -> &str {
+        self.name.rsplit('.').next().unwrap_or_default()
+    }
+
+    pub fn build(self) -> Result<TransientUnit, dbus::MethodErr> {
+        for unit in self.aux.iter() {
+            if !unit.aux.is_empty() {
+                return Err(invalid(format!(
+                    "aux unit {} can't have aux units of its own",
+                    unit.name
+                )));
+            }
+        }
+
+        let name = self.name.clone();
+        let aux = self.aux;
+        let properties = Self::properties(TransientUnitBuilder { aux: vec![], ..self })?;
+        let aux = aux
+            .into_iter()
+            .map(|unit| Ok((unit.name.clone(), Self::properties(unit)?)))
+            .collect::<Result<Vec<AuxUnitDto>, dbus::MethodErr>>()?;
+
+        Ok(TransientUnit { name, properties, aux })
+    }
+
+    fn properties(self) -> Result<Vec<PropertyDto>, dbus::MethodErr> {
+        self.validate()?;
+
+        let mut properties: Vec<PropertyDto> = vec![];
+        let mut push = |name: &str, value: Box<dyn RefArg>| properties.push((name.to_string(), Variant(value)));
+
+        if let Some(description) = self.description {
+            push("Description", Box::new(description));
+        }
+        if let Some(service_type) = self.service_type {
+            push("Type", Box::new(service_type.as_ref().to_string()));
+        }
+        if let Some(remain_after_exit) = self.remain_after_exit {
+            push("RemainAfterExit", Box::new(remain_after_exit));
+        }
+        if !self.exec_start.is_empty() {
+            let commands: Vec<(String, Vec<String>, bool)> = self
+                .exec_start
+                .into_iter()
+                .map(|c| (c.path, c.argv, c.ignore_failure))
+                .collect();
+            push("ExecStart", Box::new(commands));
+        }
+        if !self.environment.is_empty() {
+            push("Environment", Box::new(self.environment));
+        }
+        if let Some(working_directory) = self.working_directory {
+            push("WorkingDirectory", Box::new(working_directory));
+        }
+        if let Some(user) = self.user {
+            push("User", Box::new(user));
+        }
+        if let Some(group) = self.group {
+            push("Group", Box::new(group));
+        }
+        if let Some(dynamic_user) = self.dynamic_user {
+            push("DynamicUser", Box::new(dynamic_user));
+        }
+        if let Some(slice) = self.slice {
+            push("Slice", Box::new(slice));
+        }
+        if !self.pids.is_empty() {
+            push("PIDs", Box::new(self.pids));
+        }
+        if !self.timers_calendar.is_empty() {
+            push("TimersCalendar", Box::new(self.timers_calendar));
+        }
+        if !self.timers_monotonic.is_empty() {
+            push("TimersMonotonic", Box::new(self.timers_monotonic));
+        }
+        if let Some(persistent) = self.persistent {
+            push("Persistent", Box::new(persistent));
+        }
+        if let Some(delay) = self.randomized_delay {
+            push("RandomizedDelayUSec", Box::new(delay.as_usec()));
+        }
+        if let Some(accuracy) = self.accuracy {
+            push("AccuracyUSec", Box::new(accuracy.as_usec()));
+        }
+        if let Some(percent) = self.cpu_quota_percent {
+            push("CPUQuotaPerSecUSec", Box::new(percent * 10_000));
+        }
+        if let Some(weight) = self.cpu_weight {
+            push("CPUWeight", Box::new(weight));
+        }
+        if let Some(bytes) = self.memory_max {
+            push("MemoryMax", Box::new(bytes));
+        }
+        if let Some(bytes) = self.memory_high {
+            push("MemoryHigh", Box::new(bytes));
+        }
+        if let Some(bytes) = self.memory_low {
+            push("MemoryLow", Box::new(bytes));
+        }
+        if let Some(tasks) = self.tasks_max {
+            push("TasksMax", Box::new(tasks));
+        }
+        if let Some(weight) = self.io_weight {
+            push("IOWeight", Box::new(weight));
+        }
+
+        let mut dependencies: Vec<(Dependency, Vec<String>)> = vec![];
+        for (dependency, unit) in self.dependencies {
+            match dependencies.iter_mut().find(|(d, _)| *d == dependency) {
+                Some((_, units)) => units.push(unit),
+                None => dependencies.push((dependency, vec![unit])),
+            }
+        }
+        for (dependency, units) in dependencies {
+            push(dependency.as_ref(), Box::new(units));
+        }
+
+        properties.extend(self.properties);
+        Ok(properties)
+    }
+
+    fn validate(&self) -> Result<(), dbus::MethodErr> {
+        let unit_type = self.unit_type();
+        let prefix = &self.name[..self.name.len() - unit_type.len()];
+        if prefix.len() < 2 || !UNIT_TYPES.contains(&unit_type) {
+            return Err(invalid(format!("{} is not a valid unit name", self.name)));
+        }
+        if !prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ":-_.\\@".contains(c))
+        {
+            return Err(invalid(format!(
+                "{} contains characters not allowed in unit names",
+                self.name
+            )));
+        }
+
+        let is_service = unit_type == "service";
+        let has_exec_settings = !self.exec_start.is_empty()
+            || self.remain_after_exit.is_some()
+            || self.service_type.is_some()
+            || self.working_directory.is_some()
+            || self.user.is_some()
+            || self.group.is_some()
+            || self.dynamic_user.is_some()
+            || !self.environment.is_empty();
+        if is_service && self.exec_start.is_empty() {
+            return Err(invalid(format!("{} needs at least one ExecStart command", self.name)));
+        }
+        if !is_service && has_exec_settings {
+            return Err(invalid(format!(
+                "{} is not a service; exec settings don't apply",
+                self.name
+            )));
+        }
+        if unit_type == "scope" && self.pids.is_empty() {
+            return Err(invalid(format!("{} needs at least one PID", self.name)));
+        }
+        if unit_type != "scope" && !self.pids.is_empty() {
+            return Err(invalid(format!("{} is not a scope; PIDs don't apply", self.name)));
+        }
+        let has_timer_settings =
+            self.persistent.is_some() || self.randomized_delay.is_some() || self.accuracy.is_some();
+        let has_triggers = !self.timers_calendar.is_empty() || !self.timers_monotonic.is_empty();
+        if unit_type == "timer" && !has_triggers {
+            return Err(invalid(format!(
+                "{} needs OnCalendar or a monotonic trigger",
+                self.name
+            )));
+        }
+        if unit_type != "timer" && (has_triggers || has_timer_settings) {
+            return Err(invalid(format!(
+                "{} is not a timer; timer settings don't apply",
+                self.name
+            )));
+        }
+        for (_, spec) in self.timers_calendar.iter() {
+            spec.parse::<CalendarSpec>()
+                .map_err(|e| invalid(format!("{}: OnCalendar={}: {}", self.name, spec, e)))?;
+        }
+
+        for command in self.exec_start.iter() {
+            if command.path.is_empty() || command.argv.is_empty() {
+                return Err(invalid(format!("{} has an empty ExecStart command", self.name)));
+            }
+        }
+        for assignment in self.environment.iter() {
+            if assignment.starts_with('=') {
+                return Err(invalid(format!("invalid Environment assignment {}", assignment)));
+            }
+        }
+        if let Some(working_directory) = &self.working_directory {
+            if !working_directory.starts_with('/') && working_directory != "~" {
+                return Err(invalid(format!(
+                    "WorkingDirectory {} must be absolute",
+                    working_directory
+                )));
+            }
+        }
+        if let Some(slice) = &self.slice {
+            if !slice.ends_with(".slice") {
+                return Err(invalid(format!("{} is not a slice", slice)));
+            }
+        }
+        if let Some(0) = self.cpu_quota_percent {
+            return Err(invalid("CPUQuota must be greater than 0%"));
+        }
+        for (setting, weight) in [("CPUWeight", self.cpu_weight), ("IOWeight", self.io_weight)].iter() {
+            if let Some(weight) = weight {
+                if *weight < 1 || *weight > 10_000 {
+                    return Err(invalid(format!(
+                        "{} must be between 1 and 10000, got {}",
+                        setting, weight
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A validated transient unit, ready to be handed to `StartTransientUnit`.
+#[derive(Debug)]
+pub struct TransientUnit {
+    pub name: String,
+    pub properties: Vec<PropertyDto>,
+    pub aux: Vec<AuxUnitDto>,
+}
+
+impl TransientUnit {
+    /// Borrows the properties in the shape `Systemd1Manager::start_transient_unit` takes.
+    pub fn as_args(&self) -> (PropertyArgs<'_>, AuxUnitArgs<'_>) {
+        let aux = self
+            .aux
+            .iter()
+            .map(|(name, properties)| (name.as_str(), borrow_properties(properties)))
+            .collect();
+        (borrow_properties(&self.properties), aux)
+    }
+
+    pub async fn start<M: Systemd1Manager + Sync + ?Sized>(
+        &self,
+        manager: &M,
+        mode: &Mode,
+    ) -> Result<dbus::Path<'static>, dbus::MethodErr> {
+        let (properties, aux) = self.as_args();
+        manager.start_transient_unit(&self.name, mode, properties, aux).await
+    }
+}
+
+pub(crate) fn borrow_properties(properties: &[PropertyDto]) -> PropertyArgs<'_> {
+    properties
+        .iter()
+        .map(|(name, value)| (name.as_str(), Variant(value.0.box_clone())))
+        .collect()
+}
+
+fn invalid<T: std::fmt::Display>(message: T) -> dbus::MethodErr {
+    dbus::MethodErr::invalid_arg(&message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::RefArg;
+    use std::time::Duration;
+
+    use super::{Dependency, ExecCommand, ServiceType, TransientUnitBuilder};
+    use crate::systemd::{Mode, TimeSpan};
+
+    fn service() -> TransientUnitBuilder {
+        TransientUnitBuilder::new("batch-42.service")
+            .description("batch job 42")
+            .exec_start(ExecCommand::new(vec!["/usr/bin/env", "true"]))
+            .exec_start(ExecCommand::new(vec!["/bin/false"]).ignore_failure(true))
+            .environment("RUST_LOG", "debug")
+            .service_type(ServiceType::Oneshot)
+            .cpu_quota_percent(50)
+            .dependency(Dependency::After, "network.target")
+            .dependency(Dependency::After, "local-fs.target")
+    }
+
+    #[test]
+    fn serializes_into_start_transient_unit_signature() {
+        let unit = service()
+            .aux(TransientUnitBuilder::new("batch-42.timer").on_calendar("daily"))
+            .build()
+            .unwrap();
+        let (properties, aux) = unit.as_args();
+
+        let message = dbus::Message::new_method_call(
+            "org.freedesktop.systemd1",
+            "/org/freedesktop/systemd1",
+            "org.freedesktop.systemd1.Manager",
+            "StartTransientUnit",
+        )
+        .unwrap()
+        .append2(unit.name.as_str(), Mode::Replace)
+        .append2(properties, aux);
+
+        let mut iter = message.iter_init();
+        let mut signature = String::new();
+        loop {
+            signature.push_str(&iter.signature());
+            if !iter.next() {
+                break;
+            }
+        }
+        assert_eq!(signature, "ssa(sv)a(sa(sv))");
+    }
+
+    #[test]
+    fn builds_typed_properties() {
+        let unit = service().build().unwrap();
+        let property = |name: &str| {
+            unit.properties
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.0.box_clone())
+                .unwrap()
+        };
+
+        assert_eq!(property("Description").as_str(), Some("batch job 42"));
+        assert_eq!(property("Type").as_str(), Some("oneshot"));
+        assert_eq!(property("CPUQuotaPerSecUSec").as_u64(), Some(500_000));
+        assert_eq!(property("ExecStart").signature().to_string(), "a(sasb)");
+        assert_eq!(property("After").signature().to_string(), "as");
+        assert_eq!(unit.properties.iter().filter(|(n, _)| n == "After").count(), 1);
+    }
+
+    #[test]
+    fn builds_timer_properties() {
+        let unit = TransientUnitBuilder::new("batch-42.timer")
+            .on_calendar("Mon..Fri *-*-* 09:00:00")
+            .on_active(Duration::from_secs(90))
+            .persistent(true)
+            .accuracy("1min".parse::<TimeSpan>().unwrap())
+            .build()
+            .unwrap();
+        let signatures: Vec<(String, String)> = unit
+            .properties
+            .iter()
+            .map(|(n, v)| (n.clone(), v.0.signature().to_string()))
+            .collect();
+
+        assert_eq!(
+            signatures,
+            vec![
+                ("TimersCalendar".to_string(), "a(ss)".to_string()),
+                ("TimersMonotonic".to_string(), "a(st)".to_string()),
+                ("Persistent".to_string(), "b".to_string()),
+                ("AccuracyUSec".to_string(), "t".to_string()),
+            ]
+        );
+        assert_eq!(unit.properties[3].1 .0.as_u64(), Some(60_000_000));
+    }
+
+    #[test]
+    fn rejects_invalid_units() {
+        assert!(TransientUnitBuilder::new("batch").build().is_err());
+        assert!(TransientUnitBuilder::new("batch.service").build().is_err());
+        assert!(service().slice("batch").build().is_err());
+        assert!(service().cpu_weight(0).build().is_err());
+        assert!(service().working_directory("relative/dir").build().is_err());
+        assert!(TransientUnitBuilder::new("batch.scope")
+            .exec_start(ExecCommand::new(vec!["/bin/true"]))
+            .pids(vec![1])
+            .build()
+            .is_err());
+        assert!(TransientUnitBuilder::new("batch.scope").build().is_err());
+        assert!(TransientUnitBuilder::new("batch.scope").pids(vec![1]).build().is_ok());
+        assert!(TransientUnitBuilder::new("batch.timer")
+            .persistent(true)
+            .build()
+            .is_err());
+        assert!(service().on_calendar("daily").build().is_err());
+        assert!(TransientUnitBuilder::new("batch.timer")
+            .on_calendar("every tuesday")
+            .build()
+            .is_err());
+    }
+}
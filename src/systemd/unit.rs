@@ -14,6 +14,7 @@ pub type UnitStatusDto = (
 );
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SystemdUnitStatus {
     pub name: String,
     pub description: String,
@@ -21,9 +22,12 @@ pub struct SystemdUnitStatus {
     pub active: String,
     pub status: String,
     pub hwid: String,
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_path"))]
     pub object_path: dbus::Path<'static>,
+    #[cfg_attr(feature = "serde", serde(rename = "job_id"))]
     pub jod_id: u32,
     pub job_type: String,
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_path"))]
     pub job_path: dbus::Path<'static>,
 }
 
@@ -45,8 +49,11 @@ impl From<UnitStatusDto> for SystemdUnitStatus {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Systemd1ManagerUnitNew {
+    #[cfg_attr(feature = "serde", serde(rename = "unit"))]
     pub arg0: String,
+    #[cfg_attr(feature = "serde", serde(rename = "unit_path", with = "super::serde_path"))]
     pub arg1: dbus::Path<'static>,
 }
 
@@ -72,8 +79,11 @@ impl dbus::message::SignalArgs for Systemd1ManagerUnitNew {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Systemd1ManagerUnitRemoved {
+    #[cfg_attr(feature = "serde", serde(rename = "unit"))]
     pub arg0: String,
+    #[cfg_attr(feature = "serde", serde(rename = "unit_path", with = "super::serde_path"))]
     pub arg1: dbus::Path<'static>,
 }
 
@@ -99,6 +109,7 @@ impl dbus::message::SignalArgs for Systemd1ManagerUnitRemoved {
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Systemd1ManagerUnitFilesChanged;
 
 impl arg::AppendAll for Systemd1ManagerUnitFilesChanged {
@@ -115,3 +126,96 @@ impl dbus::message::SignalArgs for Systemd1ManagerUnitFilesChanged {
     const NAME: &'static str = "UnitFilesChanged";
     const INTERFACE: &'static str = "org.freedesktop.systemd1.Manager";
 }
+
+/// Object path systemd registers a unit under, e.g. `/org/freedesktop/systemd1/unit/sshd_2eservice`.
+pub fn unit_object_path(name: &str) -> dbus::Path<'static> {
+    let mut path = String::from("/org/freedesktop/systemd1/unit/");
+    if name.is_empty() {
+        path.push('_');
+    }
+    for (i, b) in name.bytes().enumerate() {
+        if b.is_ascii_alphabetic() || (i > 0 && b.is_ascii_digit()) {
+            path.push(b as char);
+        } else {
+            path.push_str(&format!("_{:02x}", b));
+        }
+    }
+    dbus::Path::from(path)
+}
+
+/// The unit name a unit object path was escaped from; `None` for paths that aren't unit objects.
+pub fn unit_name_from_object_path(path: &str) -> Option<String> {
+    let escaped = path.strip_prefix("/org/freedesktop/systemd1/unit/")?;
+    if escaped.is_empty() || escaped.contains('/') {
+        return None;
+    }
+    let bytes = escaped.as_bytes();
+    let mut name = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'_' {
+            let hex = escaped.get(i + 1..i + 3)?;
+            name.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            name.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(name).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unit_name_from_object_path, unit_object_path};
+
+    #[test]
+    fn escapes_unit_names_like_systemd() {
+        assert_eq!(
+            unit_object_path("sshd.service").to_string(),
+            "/org/freedesktop/systemd1/unit/sshd_2eservice"
+        );
+        assert_eq!(
+            unit_object_path("1-foo@bar.service").to_string(),
+            "/org/freedesktop/systemd1/unit/_31_2dfoo_40bar_2eservice"
+        );
+    }
+
+    #[test]
+    fn unescapes_unit_object_paths() {
+        for name in ["sshd.service", "1-foo@bar.service", "-.mount"].iter() {
+            let path = unit_object_path(name);
+            assert_eq!(unit_name_from_object_path(&path).as_deref(), Some(*name));
+        }
+        assert_eq!(unit_name_from_object_path("/org/freedesktop/systemd1/job/42"), None);
+        assert_eq!(unit_name_from_object_path("/org/freedesktop/systemd1/unit/bad_2"), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializes_object_paths_as_strings() {
+        use super::SystemdUnitStatus;
+
+        let status = SystemdUnitStatus::from((
+            "sshd.service".to_string(),
+            "OpenSSH Daemon".to_string(),
+            "loaded".to_string(),
+            "active".to_string(),
+            "running".to_string(),
+            String::new(),
+            unit_object_path("sshd.service"),
+            0,
+            String::new(),
+            dbus::Path::from("/"),
+        ));
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["object_path"], "/org/freedesktop/systemd1/unit/sshd_2eservice");
+        assert_eq!(json["job_id"], 0);
+
+        let parsed: SystemdUnitStatus = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.object_path, status.object_path);
+        let mut invalid = serde_json::to_value(&status).unwrap();
+        invalid["job_path"] = "not a path".into();
+        assert!(serde_json::from_value::<SystemdUnitStatus>(invalid).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use super::{Systemd1Manager, SystemdManager, SystemdUnitStatus};
+
+const UNIT_TYPES: &[&str] = &[
+    "service",
+    "socket",
+    "target",
+    "device",
+    "mount",
+    "automount",
+    "swap",
+    "timer",
+    "path",
+    "slice",
+    "scope",
+];
+
+// (states, patterns) arguments of ListUnitsByPatterns.
+type PatternsCall<'a> = (Vec<&'a str>, Vec<String>);
+
+/// Which units to list, like the arguments of `systemctl list-units`. Filtering by state and pattern happens in
+/// systemd, so only matching units are sent over the bus.
+///
+/// Without `all`, inactive units without a job are left out unless `states` asks for them. With `all`, unit
+/// names given as patterns without glob characters are loaded from disk, so units nothing references show up
+/// too.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitQuery {
+    states: Vec<String>,
+    patterns: Vec<String>,
+    unit_types: Vec<String>,
+    all: bool,
+}
+
+impl UnitQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A load, active or sub state to match (loaded, failed, running, ...), like `systemctl --state`.
+    pub fn state(mut self, state: &str) -> Self {
+        self.states.push(state.to_string());
+        self
+    }
+
+    /// A glob on the unit name (`ssh*`, `getty@*.service`) or a plain unit name.
+    pub fn pattern(mut self, pattern: &str) -> Self {
+        self.patterns.push(pattern.to_string());
+        self
+    }
+
+    /// A unit type (service, timer, ...), like `systemctl --type`.
+    pub fn unit_type(mut self, unit_type: &str) -> Self {
+        self.unit_types.push(unit_type.to_string());
+        self
+    }
+
+    pub fn all(mut self, all: bool) -> Self {
+        self.all = all;
+        self
+    }
+
+    /// Unit names to load with ListUnitsByNames and the arguments for ListUnitsByPatterns, if that call is
+    /// needed.
+    fn calls(&self) -> (Vec<&str>, Option<PatternsCall<'_>>) {
+        let (names, globs): (Vec<&str>, Vec<&str>) = self
+            .patterns
+            .iter()
+            .map(String::as_str)
+            .partition(|pattern| self.all && !is_glob(pattern));
+        let states = self.states.iter().map(String::as_str).collect();
+        let patterns = if !globs.is_empty() {
+            globs.into_iter().map(String::from).collect()
+        } else if !names.is_empty() {
+            return (names, None);
+        } else {
+            // No patterns: let systemd filter by type.
+            self.unit_types.iter().map(|t| format!("*.{}", t)).collect()
+        };
+        (names, Some((states, patterns)))
+    }
+
+    fn keep(&self, unit: &SystemdUnitStatus) -> bool {
+        let type_matches = self.unit_types.is_empty()
+            || self
+                .unit_types
+                .iter()
+                .any(|t| unit.name.rsplit('.').next() == Some(t.as_str()));
+        let state_matches = if !self.states.is_empty() {
+            // Units from ListUnitsByNames aren't filtered by systemd.
+            [&unit.loaded, &unit.active, &unit.status]
+                .iter()
+                .any(|state| self.states.contains(state))
+        } else {
+            self.all || unit.jod_id != 0 || unit.active != "inactive"
+        };
+        type_matches && state_matches
+    }
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+impl SystemdManager {
+    /// Lists the units matching `query`, sorted by name.
+    pub async fn query_units(&self, query: &UnitQuery) -> Result<Vec<SystemdUnitStatus>, dbus::MethodErr> {
+        if let Some(unit_type) = query.unit_types.iter().find(|t| !UNIT_TYPES.contains(&t.as_str())) {
+            return Err(dbus::MethodErr::invalid_arg(&format!(
+                "unknown unit type {}",
+                unit_type
+            )));
+        }
+        let (names, patterns) = query.calls();
+        let mut units = vec![];
+        if !names.is_empty() {
+            units.extend(self.list_units_by_names(names).await?);
+        }
+        if let Some((states, patterns)) = patterns {
+            units.extend(
+                self.list_units_by_patterns(states, patterns.iter().map(String::as_str).collect())
+                    .await?,
+            );
+        }
+        let mut units: Vec<SystemdUnitStatus> = units
+            .into_iter()
+            .map(SystemdUnitStatus::from)
+            .filter(|unit| query.keep(unit))
+            .collect();
+        units.sort_by(|a, b| a.name.cmp(&b.name));
+        units.dedup_by(|a, b| a.name == b.name);
+        Ok(units)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SystemdUnitStatus, UnitQuery};
+    use crate::unit_object_path;
+
+    fn unit(name: &str, active: &str, job_id: u32) -> SystemdUnitStatus {
+        SystemdUnitStatus::from((
+            name.to_string(),
+            String::new(),
+            "loaded".to_string(),
+            active.to_string(),
+            String::new(),
+            String::new(),
+            unit_object_path(name),
+            job_id,
+            String::new(),
+            dbus::Path::from("/"),
+        ))
+    }
+
+    #[test]
+    fn splits_patterns_between_calls() {
+        let query = UnitQuery::new().state("failed").pattern("ssh*");
+        assert_eq!(
+            query.calls(),
+            (vec![], Some((vec!["failed"], vec!["ssh*".to_string()])))
+        );
+
+        let query = UnitQuery::new().unit_type("timer").unit_type("socket");
+        let patterns = vec!["*.timer".to_string(), "*.socket".to_string()];
+        assert_eq!(query.calls(), (vec![], Some((vec![], patterns))));
+
+        // Plain names are only loaded from disk with `all`.
+        let query = UnitQuery::new().pattern("backup.service").pattern("getty@*");
+        assert_eq!(query.calls().0, Vec::<&str>::new());
+        let query = query.all(true);
+        assert_eq!(
+            query.calls(),
+            (vec!["backup.service"], Some((vec![], vec!["getty@*".to_string()])))
+        );
+        assert_eq!(
+            UnitQuery::new().all(true).pattern("a.service").calls(),
+            (vec!["a.service"], None)
+        );
+    }
+
+    #[test]
+    fn hides_inactive_units_unless_asked() {
+        let query = UnitQuery::new().unit_type("service");
+        assert!(query.keep(&unit("a.service", "active", 0)));
+        assert!(query.keep(&unit("b.service", "inactive", 7)));
+        assert!(!query.keep(&unit("c.service", "inactive", 0)));
+        assert!(!query.keep(&unit("d.timer", "active", 0)));
+
+        assert!(query.clone().all(true).keep(&unit("c.service", "inactive", 0)));
+        assert!(query.clone().state("inactive").keep(&unit("c.service", "inactive", 0)));
+        assert!(!query.state("failed").keep(&unit("a.service", "active", 0)));
+    }
+}
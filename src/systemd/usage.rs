@@ -0,0 +1,213 @@
+use dbus::arg;
+use futures::Stream;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant, SystemTime},
+};
+
+use super::{cgroup_interface, property_u64, SystemdManager};
+
+/// Resource counters of a unit at one point in time, read from the properties of its cgroup. A counter is
+/// `None` when its accounting is off for the unit (e.g. IPAccounting=no) or the unit isn't running.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UsageSample {
+    pub unit: String,
+    pub timestamp: SystemTime,
+    /// When the sample was taken, for computing rates; unaffected by clock changes.
+    #[cfg_attr(feature = "serde", serde(skip, default = "Instant::now"))]
+    pub taken_at: Instant,
+    pub cpu_usage_nsec: Option<u64>,
+    pub memory_current: Option<u64>,
+    pub tasks_current: Option<u64>,
+    pub io_read_bytes: Option<u64>,
+    pub io_write_bytes: Option<u64>,
+    pub ip_ingress_bytes: Option<u64>,
+    pub ip_egress_bytes: Option<u64>,
+}
+
+impl UsageSample {
+    pub fn from_properties(unit: &str, properties: &arg::PropMap) -> Self {
+        Self {
+            unit: unit.to_string(),
+            timestamp: SystemTime::now(),
+            taken_at: Instant::now(),
+            cpu_usage_nsec: property_u64(properties, "CPUUsageNSec"),
+            memory_current: property_u64(properties, "MemoryCurrent"),
+            tasks_current: property_u64(properties, "TasksCurrent"),
+            io_read_bytes: property_u64(properties, "IOReadBytes"),
+            io_write_bytes: property_u64(properties, "IOWriteBytes"),
+            ip_ingress_bytes: property_u64(properties, "IPIngressBytes"),
+            ip_egress_bytes: property_u64(properties, "IPEgressBytes"),
+        }
+    }
+}
+
+/// Rates between two consecutive samples of a unit. CPU is a percentage of one CPU, like `systemd-cgtop`
+/// shows it, so a unit busy on two CPUs is at 200%.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UsageRates {
+    pub cpu_percent: Option<f64>,
+    pub io_read_bytes_per_sec: Option<f64>,
+    pub io_write_bytes_per_sec: Option<f64>,
+    pub ip_ingress_bytes_per_sec: Option<f64>,
+    pub ip_egress_bytes_per_sec: Option<f64>,
+}
+
+impl UsageRates {
+    /// Rates from `previous` to `current`. A counter that went backwards (the unit restarted and its cgroup was
+    /// recreated) has no rate for this interval.
+    pub fn between(previous: &UsageSample, current: &UsageSample) -> Self {
+        let elapsed = current.taken_at.saturating_duration_since(previous.taken_at);
+        if elapsed == Duration::from_secs(0) {
+            return Self::default();
+        }
+        let secs = elapsed.as_secs_f64();
+        let rate = |previous: Option<u64>, current: Option<u64>| match (previous, current) {
+            (Some(previous), Some(current)) if current >= previous => Some((current - previous) as f64 / secs),
+            _ => None,
+        };
+        Self {
+            cpu_percent: rate(previous.cpu_usage_nsec, current.cpu_usage_nsec).map(|ns| ns / 1e9 * 100.0),
+            io_read_bytes_per_sec: rate(previous.io_read_bytes, current.io_read_bytes),
+            io_write_bytes_per_sec: rate(previous.io_write_bytes, current.io_write_bytes),
+            ip_ingress_bytes_per_sec: rate(previous.ip_ingress_bytes, current.ip_ingress_bytes),
+            ip_egress_bytes_per_sec: rate(previous.ip_egress_bytes, current.ip_egress_bytes),
+        }
+    }
+}
+
+/// A sample and, from the second sample of a unit on, the rates since the previous one.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitUsage {
+    pub sample: UsageSample,
+    pub rates: Option<UsageRates>,
+}
+
+struct Sampler {
+    manager: SystemdManager,
+    units: Vec<String>,
+    interval: tokio::time::Interval,
+    previous: HashMap<String, UsageSample>,
+    pending: VecDeque<Result<UnitUsage, dbus::MethodErr>>,
+}
+
+impl Sampler {
+    async fn sample(&mut self) {
+        self.interval.tick().await;
+        for unit in self.units.iter() {
+            let usage = match read_sample(&self.manager, unit).await {
+                Ok(sample) => {
+                    let rates = self
+                        .previous
+                        .get(unit)
+                        .map(|previous| UsageRates::between(previous, &sample));
+                    self.previous.insert(unit.clone(), sample.clone());
+                    Ok(UnitUsage { sample, rates })
+                }
+                Err(e) => {
+                    self.previous.remove(unit);
+                    Err(e)
+                }
+            };
+            self.pending.push_back(usage);
+        }
+    }
+}
+
+async fn read_sample(manager: &SystemdManager, unit: &str) -> Result<UsageSample, dbus::MethodErr> {
+    let interface = match cgroup_interface(unit) {
+        Some(interface) => interface,
+        None => return Err(dbus::MethodErr::invalid_arg(&format!("{} has no cgroup", unit))),
+    };
+    let properties = manager.get_unit_properties(unit, interface).await?;
+    Ok(UsageSample::from_properties(unit, &properties))
+}
+
+impl SystemdManager {
+    /// Samples the resource usage of `units` every `interval`, first right away, yielding one item per unit
+    /// and round. A unit that can't be read yields an error and the stream goes on with the next one.
+    pub fn sample_usage(
+        &self,
+        units: Vec<String>,
+        interval: Duration,
+    ) -> impl Stream<Item = Result<UnitUsage, dbus::MethodErr>> + Send {
+        let mut timer = tokio::time::interval(interval);
+        timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let sampler = Sampler {
+            manager: self.clone(),
+            units,
+            interval: timer,
+            previous: HashMap::new(),
+            pending: VecDeque::new(),
+        };
+        futures::stream::unfold(sampler, |mut sampler| async move {
+            if sampler.units.is_empty() {
+                return None;
+            }
+            while sampler.pending.is_empty() {
+                sampler.sample().await;
+            }
+            sampler.pending.pop_front().map(|usage| (usage, sampler))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::arg::{PropMap, RefArg, Variant};
+    use std::time::Duration;
+
+    use super::{UsageRates, UsageSample};
+
+    fn sample(properties: &[(&str, u64)]) -> UsageSample {
+        let mut map = PropMap::new();
+        for (name, value) in properties.iter() {
+            map.insert(name.to_string(), Variant(Box::new(*value) as Box<dyn RefArg>));
+        }
+        UsageSample::from_properties("batch.service", &map)
+    }
+
+    #[test]
+    fn reads_counters() {
+        let sample = sample(&[
+            ("CPUUsageNSec", 5_000_000),
+            ("MemoryCurrent", 1 << 20),
+            ("TasksCurrent", 0),
+            ("IPIngressBytes", u64::MAX),
+        ]);
+        assert_eq!(sample.cpu_usage_nsec, Some(5_000_000));
+        assert_eq!(sample.memory_current, Some(1 << 20));
+        assert_eq!(sample.tasks_current, Some(0));
+        assert_eq!(sample.ip_ingress_bytes, None);
+        assert_eq!(sample.io_read_bytes, None);
+    }
+
+    #[test]
+    fn derives_rates_from_consecutive_samples() {
+        let previous = sample(&[
+            ("CPUUsageNSec", 1_000_000_000),
+            ("IOReadBytes", 1000),
+            ("IOWriteBytes", 5000),
+            ("IPEgressBytes", 0),
+        ]);
+        let mut current = sample(&[
+            ("CPUUsageNSec", 4_000_000_000),
+            ("IOReadBytes", 3000),
+            ("IOWriteBytes", 100),
+            ("IPEgressBytes", 4096),
+        ]);
+        current.taken_at = previous.taken_at + Duration::from_secs(2);
+
+        let rates = UsageRates::between(&previous, &current);
+        assert_eq!(rates.cpu_percent, Some(150.0));
+        assert_eq!(rates.io_read_bytes_per_sec, Some(1000.0));
+        assert_eq!(rates.io_write_bytes_per_sec, None);
+        assert_eq!(rates.ip_egress_bytes_per_sec, Some(2048.0));
+        assert_eq!(rates.ip_ingress_bytes_per_sec, None);
+
+        assert_eq!(UsageRates::between(&previous, &previous), UsageRates::default());
+    }
+}
@@ -0,0 +1,176 @@
+use futures::Future;
+use std::{io, time::Duration};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use super::{Notifier, NotifyState};
+
+/// What a watchdog health check found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Health {
+    /// Ping the watchdog.
+    Healthy,
+    /// Skip this ping; the service manager acts if the service stays unhealthy for the whole interval.
+    Unhealthy,
+    /// Send WATCHDOG=trigger so the service manager acts right away, and stop pinging.
+    Deadlocked,
+}
+
+/// A task pinging the service manager's watchdog at half the WatchdogSec= interval. Pinging stops when this is
+/// dropped.
+#[derive(Debug)]
+pub struct Watchdog {
+    interval: Duration,
+    notifier: Notifier,
+    task: JoinHandle<()>,
+}
+
+impl Watchdog {
+    /// Pings `notifier` every `interval / 2` while `health_check` reports [`Health::Healthy`]. A health check
+    /// still running after a whole `interval` is taken as a deadlock.
+    pub fn spawn<F, Fut>(notifier: Notifier, interval: Duration, mut health_check: F) -> Self
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Health> + Send,
+    {
+        let pinger = notifier.clone();
+        let task = tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval / 2);
+            loop {
+                timer.tick().await;
+                let health = match tokio::time::timeout(interval, health_check()).await {
+                    Ok(health) => health,
+                    Err(_) => {
+                        warn!("watchdog health check still running after {:?}", interval);
+                        Health::Deadlocked
+                    }
+                };
+                let (state, last) = match health {
+                    Health::Healthy => (NotifyState::Watchdog, false),
+                    Health::Unhealthy => {
+                        debug!("unhealthy, skipping watchdog ping");
+                        continue;
+                    }
+                    Health::Deadlocked => (NotifyState::WatchdogTrigger, true),
+                };
+                if let Err(e) = pinger.notify(&[state]) {
+                    warn!("watchdog notification failed: {:?}", e);
+                }
+                if last {
+                    break;
+                }
+            }
+        });
+        Self {
+            interval,
+            notifier,
+            task,
+        }
+    }
+
+    /// The WatchdogSec= interval; pings go out at half of it.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Stops pinging and sends WATCHDOG=trigger, for failures the application detects itself.
+    pub fn trigger(&self) -> io::Result<()> {
+        self.task.abort();
+        self.notifier.notify(&[NotifyState::WatchdogTrigger])
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The watchdog interval set for this process, like sd_watchdog_enabled(3): `$WATCHDOG_USEC`, unless
+/// `$WATCHDOG_PID` names another process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    std::env::var("WATCHDOG_USEC")
+        .ok()?
+        .parse::<u64>()
+        .ok()
+        .filter(|usec| *usec > 0)
+        .map(Duration::from_micros)
+}
+
+/// Starts pinging the watchdog if the service has WatchdogSec= set and a `$NOTIFY_SOCKET`; `None` otherwise.
+/// Must be called from within a tokio runtime.
+pub fn spawn_watchdog<F, Fut>(health_check: F) -> Option<Watchdog>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Health> + Send,
+{
+    let interval = watchdog_interval()?;
+    let notifier = Notifier::from_env()?;
+    Some(Watchdog::spawn(notifier, interval, health_check))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::{Health, Watchdog};
+    use crate::{Notifier, NotifyListener};
+
+    fn listener(test: &str) -> NotifyListener {
+        NotifyListener::bind_abstract(&format!("dbus-systemd-{}-{}", test, std::process::id())).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn pings_while_healthy() {
+        let listener = listener("watchdog");
+        let checks = Arc::new(AtomicUsize::new(0));
+        let counter = checks.clone();
+        let watchdog = Watchdog::spawn(Notifier::new(listener.socket()), Duration::from_millis(40), move || {
+            let check = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match check {
+                    0 | 2 => Health::Healthy,
+                    1 => Health::Unhealthy,
+                    _ => Health::Deadlocked,
+                }
+            }
+        });
+        assert_eq!(listener.recv().unwrap(), vec!["WATCHDOG=1"]);
+        assert_eq!(listener.recv().unwrap(), vec!["WATCHDOG=1"]);
+        assert_eq!(listener.recv().unwrap(), vec!["WATCHDOG=trigger"]);
+        assert_eq!(checks.load(Ordering::SeqCst), 4);
+        assert_eq!(watchdog.interval(), Duration::from_millis(40));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn triggers_on_stuck_health_check() {
+        let listener = listener("watchdog-stuck");
+        let _watchdog = Watchdog::spawn(Notifier::new(listener.socket()), Duration::from_millis(20), || {
+            futures::future::pending::<Health>()
+        });
+        assert_eq!(listener.recv().unwrap(), vec!["WATCHDOG=trigger"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn triggers_on_request() {
+        let listener = listener("watchdog-trigger");
+        let watchdog = Watchdog::spawn(Notifier::new(listener.socket()), Duration::from_secs(60), || async {
+            Health::Healthy
+        });
+        assert_eq!(listener.recv().unwrap(), vec!["WATCHDOG=1"]);
+        watchdog.trigger().unwrap();
+        assert_eq!(listener.recv().unwrap(), vec!["WATCHDOG=trigger"]);
+    }
+}